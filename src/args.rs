@@ -1,6 +1,6 @@
 //! Command line arguments.
 
-use std::path::PathBuf;
+use std::{num::NonZeroUsize, path::PathBuf};
 use structopt::StructOpt;
 use bunt::termcolor::ColorChoice;
 
@@ -15,11 +15,26 @@ pub struct Args {
     #[structopt(long, short)]
     pub config: Option<PathBuf>,
 
-    /// If this flag is specified, the loaded configuration is printed for
-    /// debugging.
+    /// If this flag is specified, the loaded configuration is printed (as
+    /// YAML) for debugging.
     #[structopt(long)]
     pub debug_config: bool,
 
+    /// Sets a variable in the root context, e.g. `--set profile=release`. Can
+    /// be specified multiple times.
+    #[structopt(long = "set", parse(try_from_str = parse_key_value), number_of_values = 1)]
+    pub vars: Vec<(String, String)>,
+
+    /// Makes all `watch` operations poll the file system instead of relying on
+    /// change notifications, as if `poll` was specified for them. Useful for
+    /// file systems that don't report changes, e.g. network file systems.
+    #[structopt(long, global = true)]
+    pub poll: bool,
+
+    /// Maximum number of tasks that run at the same time.
+    #[structopt(long, short, default_value = "1", global = true)]
+    pub jobs: NonZeroUsize,
+
     /// Verbosity level: `-v` or `-vv` allowed.
     #[structopt(short, parse(from_occurrences))]
     pub verbose: u8,
@@ -31,10 +46,32 @@ pub struct Args {
 
 #[derive(StructOpt)]
 pub enum Command {
-    /// Run a specific task instead of the default one
+    /// Run specific tasks instead of the default one
     Run {
-        /// Name of the task that is supposed to run.
-        task: String,
+        /// Names of the tasks that are supposed to run. Tasks that do not
+        /// depend on one another run concurrently (see `--jobs`).
+        #[structopt(required = true, min_values = 1)]
+        tasks: Vec<String>,
+
+        /// Argument for a parameter of the tasks, e.g. `--param env=staging`.
+        /// Passed to all specified tasks that have a parameter of that name.
+        /// Can be specified multiple times.
+        #[structopt(long = "param", short = "p", parse(try_from_str = parse_key_value), number_of_values = 1)]
+        params: Vec<(String, String)>,
+    },
+
+    /// List all tasks defined in the configuration
+    List,
+
+    /// Check the configuration for errors and likely mistakes without running
+    /// anything
+    Check,
+}
+
+fn parse_key_value(input: &str) -> Result<(String, String), String> {
+    match input.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.into(), value.into())),
+        _ => Err(format!("'{}' is not of the form `key=value`", input)),
     }
 }
 
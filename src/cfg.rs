@@ -1,56 +1,79 @@
 //! Configuration, usually loaded from `floof.yaml`.
 
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap, HashSet},
     fmt,
     fs,
-    path::Path,
+    path::{Path, PathBuf},
+    sync::Arc,
 };
 use serde::{Deserializer, Deserialize, de::{self, MapAccess, SeqAccess, Visitor}};
 use crate::{
     Operation, Task,
     prelude::*,
+    diagnostic::{self, Diagnostic, Location, OperationTree, SourceFile},
     op::{
-        Command, Concurrently, Copy, Http, OnChange, Operations, Reload, RunTask,
-        SetWorkDir, Watch,
+        self,
+        Cached, Command, Concurrently, Copy, Dotenv, Http, OnChange, Reload, RunTask,
+        SetEnv, SetVar, SetWorkDir, Timeout, Watch,
     },
+    task::{AtOperation, RawTask},
 };
 
 
 /// The default filename from which to load the configuration.
 pub const DEFAULT_FILENAME: &str = "floof.yaml";
 
+/// The reserved top level key to include other configuration files.
+const INCLUDE_KEY: &str = "include";
 
-/// The root configuration object.
-#[derive(Debug, Deserialize)]
-#[serde(from = "HashMap<String, Operations>")]
+
+/// The root configuration object. Contains the tasks of the main
+/// configuration file and all included files.
+#[derive(Debug)]
 pub struct Config {
     pub tasks: HashMap<String, Task>,
 }
 
-impl From<HashMap<String, Operations>> for Config {
-    fn from(tasks: HashMap<String, Operations>) -> Self {
-        // We duplicate the name of the task here. This is only for convenience
-        // to has `name` in `Task`.
-        let tasks = tasks.into_iter()
-            .map(|(name, operations)| (name.clone(), Task { name, operations }))
-            .collect();
-
-        Self { tasks }
-    }
+/// The contents of a single configuration file.
+struct ConfigFile {
+    /// Included files by namespace.
+    includes: BTreeMap<String, PathBuf>,
+    tasks: HashMap<String, RawTask>,
 }
 
 impl Config {
     /// Loads and validates the configuration from the specified path.
     pub fn load(path: Option<&Path>) -> Result<Self> {
+        Self::find_and_load(path, true)
+    }
+
+    /// Loads the configuration from the specified path without checking for
+    /// logic errors. See `errors` for that.
+    pub fn load_unvalidated(path: Option<&Path>) -> Result<Self> {
+        Self::find_and_load(path, false)
+    }
+
+    /// Returns the configuration in configuration file syntax, with tasks
+    /// sorted by name. Used by `--debug-config`.
+    pub fn describe(&self) -> serde_yaml::Value {
+        let mut tasks = self.tasks.values().collect::<Vec<_>>();
+        tasks.sort_by_key(|t| &t.name);
+        tasks.into_iter()
+            .map(|t| (t.name.clone().into(), t.describe()))
+            .collect::<serde_yaml::Mapping>()
+            .into()
+    }
+
+    fn find_and_load(path: Option<&Path>, validate: bool) -> Result<Self> {
         let default_path = Path::new(DEFAULT_FILENAME);
         match path {
             Some(path) => {
-                Config::load_from(path)
+                Config::load_from(path, validate)
                     .context(format!("failed to load configuration from '{}'", path.display()))
             }
             None if default_path.exists() && default_path.is_file() => {
-                Config::load_from(default_path).with_context(|| {
+                Config::load_from(default_path, validate).with_context(|| {
                     format!(
                         "failed to load configuration from default location '{}' \
                             (file exists, but is invalid)",
@@ -68,33 +91,245 @@ impl Config {
         }
     }
 
-    fn load_from(path: impl AsRef<Path>) -> Result<Self> {
-        let path = path.as_ref();
-        let content = fs::read(path)
-            .context(format!("failed to read contents of '{}'", path.display()))?;
-        let config: Self = serde_yaml::from_slice(&content)
-            .context("failed to deserialize YAML file")?;
-        config.validate()
-            .context("invalid config file: logic errors were found")?;
+    fn load_from(path: impl AsRef<Path>, validate: bool) -> Result<Self> {
+        let mut config = Self { tasks: HashMap::new() };
+        config.load_file(path.as_ref(), "", None, &mut vec![])?;
+
+        if validate {
+            if let Some(e) = config.errors().into_iter().next() {
+                return Err(e.context("invalid config file: logic errors were found"));
+            }
+        }
 
         Ok(config)
     }
 
-    fn validate(&self) -> Result<()> {
-        for task in self.tasks.values() {
-            task.validate(self)
-                .context(format!("invalid configuration for task '{}'", task.name))?;
+    /// Adds the tasks of the given file and of all files it includes to
+    /// `self`. `namespace` is prepended to the names of these tasks and to
+    /// the names of tasks they refer to. `dir` is the directory of the file
+    /// relative to the main configuration file (`None` for the main file).
+    /// `stack` contains the files that are currently being loaded to detect
+    /// include cycles.
+    fn load_file(
+        &mut self,
+        path: &Path,
+        namespace: &str,
+        dir: Option<&Path>,
+        stack: &mut Vec<PathBuf>,
+    ) -> Result<()> {
+        let content = fs::read_to_string(path)
+            .context(format!("failed to read contents of '{}'", path.display()))?;
+        let canonical = path.canonicalize()
+            .context(format!("failed to canonicalize '{}'", path.display()))?;
+        if stack.contains(&canonical) {
+            bail!("'{}' includes itself (directly or indirectly)", path.display());
+        }
+        let file = Arc::new(SourceFile { path: path.to_owned(), content });
+
+        let config_file: ConfigFile = serde_yaml::from_str(&file.content)
+            .map_err(|e| match e.location() {
+                Some(loc) => {
+                    // The location is shown by the diagnostic already.
+                    let message = e.to_string();
+                    let suffix = format!(" at line {} column {}", loc.line(), loc.column());
+                    let message = message.strip_suffix(&suffix).unwrap_or(&message);
+                    let pos = diagnostic::Position { line: loc.line(), column: loc.column() };
+                    anyhow!(Diagnostic::new(message, Location { file: file.clone(), pos }))
+                }
+                None => e.into(),
+            })
+            .context("failed to deserialize YAML file")?;
+
+        let mut positions = diagnostic::task_positions(&file.content);
+        for (name, raw) in config_file.tasks {
+            let positions = positions.remove(&name);
+            let mut task = raw.into_task(format!("{}{}", namespace, name));
+
+            // Task names in included files refer to tasks of the same file.
+            if !namespace.is_empty() {
+                for name in task.depends.iter_mut().chain(&mut task.after) {
+                    name.insert_str(0, namespace);
+                }
+                op::qualify_task_names(&mut task.operations, namespace);
+                task.dir = dir.map(Path::to_path_buf);
+            }
+
+            if let Some(p) = positions {
+                let location = |pos| Location { file: file.clone(), pos };
+                task.location = Some(location(p.key));
+                task.operation_locations = p.operations.into_iter()
+                    .map(|tree| tree.map(&location))
+                    .collect();
+            }
+
+            if self.tasks.contains_key(&task.name) {
+                let e = anyhow!("task '{}' is defined more than once", task.name);
+                return Err(locate(e, task.location.as_ref()));
+            }
+            self.tasks.insert(task.name.clone(), task);
+        }
+
+        // Paths of included files are relative to the including file.
+        stack.push(canonical);
+        for (name, include) in config_file.includes {
+            if name.is_empty() || name.contains(':') {
+                bail!("invalid namespace '{}' for included file: must be non-empty and \
+                    must not contain ':'", name);
+            }
+
+            let include_path = path.parent().unwrap_or(Path::new("")).join(&include);
+            let include_dir = dir.unwrap_or(Path::new("")).join(&include);
+            let include_dir = include_dir.parent().unwrap_or(Path::new(""));
+            let namespace = format!("{}{}:", namespace, name);
+            self.load_file(&include_path, &namespace, Some(include_dir), stack)
+                .with_context(|| format!(
+                    "failed to load '{}' included as '{}'",
+                    include_path.display(),
+                    namespace.trim_end_matches(':'),
+                ))?;
         }
+        stack.pop();
 
         Ok(())
     }
+
+    /// Returns all logic errors in the configuration, sorted by task name.
+    pub fn errors(&self) -> Vec<Error> {
+        let mut tasks = self.tasks.values().collect::<Vec<_>>();
+        tasks.sort_by_key(|t| &t.name);
+        let mut errors = tasks.into_iter()
+            .flat_map(|task| task.validate(self).into_iter().map(move |e| {
+                let location = match e.downcast_ref::<AtOperation>() {
+                    Some(at) => OperationTree::find(&task.operation_locations, &at.path),
+                    None => task.location.as_ref(),
+                };
+                let e = e.context(format!("invalid configuration for task '{}'", task.name));
+                locate(e, location)
+            }))
+            .collect::<Vec<_>>();
+
+        if let Some(cycle) = self.find_cycle() {
+            let e = anyhow!("cycle between tasks detected: {}", cycle.join(" -> "));
+            errors.push(locate(e, self.tasks[cycle[0]].location.as_ref()));
+        }
+
+        errors
+    }
+
+    /// Finds a task that depends on itself, directly or indirectly, and
+    /// returns the cycle (e.g. `[a, b, a]`). Edges are `depends`, `after` (the
+    /// scheduler would wait forever) and `run-task` operations, nested
+    /// anywhere in the task (infinite recursion). References to unknown tasks
+    /// are ignored, they are reported by `Task::validate`.
+    fn find_cycle(&self) -> Option<Vec<&str>> {
+        fn visit<'a>(
+            config: &'a Config,
+            name: &'a str,
+            path: &mut Vec<&'a str>,
+            done: &mut HashSet<&'a str>,
+        ) -> Result<(), Vec<&'a str>> {
+            if let Some(pos) = path.iter().position(|&n| n == name) {
+                let mut cycle = path[pos..].to_vec();
+                cycle.push(name);
+                return Err(cycle);
+            }
+            if done.contains(name) {
+                return Ok(());
+            }
+
+            let task = match config.tasks.get(name) {
+                Some(task) => task,
+                None => return Ok(()),
+            };
+            let mut next = task.depends.iter().chain(&task.after).map(|s| s.as_str()).collect();
+            op::referenced_tasks(&task.operations, &mut next);
+
+            path.push(name);
+            for next in next {
+                visit(config, next, path, done)?;
+            }
+            path.pop();
+            done.insert(name);
+
+            Ok(())
+        }
+
+        let mut names = self.tasks.keys().collect::<Vec<_>>();
+        names.sort();
+        let mut done = HashSet::new();
+        names.into_iter().find_map(|name| visit(self, name, &mut vec![], &mut done).err())
+    }
+}
+
+/// Turns the error into a diagnostic pointing to the given location, if any.
+fn locate(e: Error, location: Option<&Location>) -> Error {
+    match location {
+        Some(location) => anyhow!(Diagnostic::new(format!("{:#}", e), location.clone())),
+        None => e,
+    }
+}
+
+impl<'de> Deserialize<'de> for ConfigFile {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct FileVisitor;
+        impl<'de> Visitor<'de> for FileVisitor {
+            type Value = ConfigFile;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a map of tasks")
+            }
+
+            fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+            where
+                A: MapAccess<'de>,
+            {
+                let mut out = ConfigFile { includes: BTreeMap::new(), tasks: HashMap::new() };
+                while let Some(key) = map.next_key::<String>()? {
+                    if key == INCLUDE_KEY {
+                        out.includes = map.next_value()?;
+                    } else {
+                        let task = map.next_value()?;
+                        out.tasks.insert(key, task);
+                    }
+                }
+
+                Ok(out)
+            }
+        }
+
+        deserializer.deserialize_map(FileVisitor)
+    }
 }
 
 
 // Helper macro to avoid code duplication. Implements `Deserialize` for
-// `Box<dyn Operation>`.
+// `Box<dyn Operation>` and defines `deserialize_tagged_op`.
 macro_rules! impl_deserialize_for_op {
     ($($ty:ident),* $(,)?) => {
+        /// Deserializes the operation with the given keyword from the next
+        /// value of `map`. Used when the key of an operation map was already
+        /// read.
+        pub fn deserialize_tagged_op<'de, A>(
+            keyword: &str,
+            map: &mut A,
+        ) -> Result<Box<dyn Operation>, A::Error>
+        where
+            A: MapAccess<'de>,
+        {
+            match keyword {
+                $(
+                    $ty::KEYWORD => {
+                        let op: $ty = map.next_value()?;
+                        Ok(Box::new(op))
+                    }
+                )*
+                other => Err(de::Error::unknown_variant(other, &[$($ty::KEYWORD),+])),
+            }
+        }
+
         impl<'de> Deserialize<'de> for Box<dyn Operation> {
             fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
             where
@@ -136,15 +371,7 @@ macro_rules! impl_deserialize_for_op {
                         let tag = map.next_key::<String>()?
                             .ok_or_else(|| de::Error::invalid_length(0, &"1"))?;
 
-                        match tag.as_str() {
-                            $(
-                                $ty::KEYWORD => {
-                                    let op: $ty = map.next_value()?;
-                                    Ok(Box::new(op))
-                                }
-                            )*
-                            other => Err(de::Error::unknown_variant(other, &[$($ty::KEYWORD),+])),
-                        }
+                        deserialize_tagged_op(&tag, &mut map)
                     }
                 }
 
@@ -159,5 +386,6 @@ macro_rules! impl_deserialize_for_op {
 }
 
 impl_deserialize_for_op![
-    Command, Concurrently, Copy, Http, OnChange, Reload, RunTask, SetWorkDir, Watch,
+    Cached, Command, Concurrently, Copy, Dotenv, Http, OnChange, Reload, RunTask,
+    SetEnv, SetVar, SetWorkDir, Timeout, Watch,
 ];
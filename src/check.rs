@@ -0,0 +1,98 @@
+//! The `floof check` subcommand: reports all errors and warnings in the
+//! configuration without running anything.
+
+use std::{collections::HashSet, io::Write};
+use crate::{
+    Operation,
+    prelude::*,
+    op::{self, Http, LintScope, SetWorkDir, WorkDir},
+};
+
+
+/// Prints all problems of the configuration and returns the exit code: 0 if
+/// no problems were found, 1 otherwise.
+pub fn run(ctx: &Context) -> Result<i32> {
+    let config = &ctx.config;
+    let errors = config.errors();
+
+    // Linting requires a valid configuration (e.g. all referenced tasks have
+    // to exist).
+    let warnings = if errors.is_empty() { warnings(ctx) } else { vec![] };
+
+    let w = crate::ui::WRITER.get().expect("bug: ui not initialized yet");
+    let mut buf = w.buffer();
+    for e in &errors {
+        bunt::writeln!(buf, "{$red+bold}error:{/$} {:#}", e)?;
+    }
+    for warning in &warnings {
+        bunt::writeln!(buf, "{$yellow+bold}warning:{/$} {}", warning)?;
+    }
+
+    if errors.is_empty() && warnings.is_empty() {
+        bunt::writeln!(buf, "{$green+bold}No problems found{/$} ({} tasks)", config.tasks.len())?;
+    } else {
+        writeln!(buf)?;
+        writeln!(buf, "Found {} error(s) and {} warning(s)", errors.len(), warnings.len())?;
+    }
+    w.print(&buf)?;
+
+    Ok(if errors.is_empty() && warnings.is_empty() { 0 } else { 1 })
+}
+
+/// Collects the warnings (see `Operation::lint`) of all tasks, sorted by task
+/// name.
+fn warnings(ctx: &Context) -> Vec<String> {
+    fn lint(ops: &[Box<dyn Operation>], mut scope: LintScope<'_>, out: &mut Vec<String>) {
+        // `http` makes the server available for the whole context, regardless
+        // of the position in the list.
+        if ops.iter().any(|op| op.keyword() == Http::KEYWORD) {
+            scope.has_http = Some(true);
+        }
+
+        for op in ops {
+            out.extend(
+                op.lint(&scope).into_iter()
+                    .map(|warning| format!("operation '{}': {}", op.keyword(), warning))
+            );
+            lint(op.children(), scope, out);
+
+            if op.keyword() == SetWorkDir::KEYWORD {
+                scope.workdir = None;
+            }
+        }
+    }
+
+    let config = &ctx.config;
+    let root_workdir = ctx.root_frame().get_var::<WorkDir>().expect("bug: no root workdir").0;
+
+    // Tasks started via `run-task` inherit the context of the calling task,
+    // so we know less about them.
+    let mut referenced = Vec::new();
+    for task in config.tasks.values() {
+        op::referenced_tasks(&task.operations, &mut referenced);
+    }
+    let referenced = referenced.into_iter().collect::<HashSet<_>>();
+
+    let mut tasks = config.tasks.values().collect::<Vec<_>>();
+    tasks.sort_by_key(|t| &t.name);
+
+    let mut out = Vec::new();
+    for task in tasks {
+        // Tasks of included files always start in the directory of that file.
+        let workdir = match &task.dir {
+            Some(dir) => root_workdir.join(dir),
+            None => root_workdir.clone(),
+        };
+        let is_referenced = referenced.contains(task.name.as_str());
+        let scope = LintScope {
+            workdir: if is_referenced && task.dir.is_none() { None } else { Some(&workdir) },
+            has_http: if is_referenced { None } else { Some(false) },
+        };
+
+        let mut warnings = Vec::new();
+        lint(&task.operations, scope, &mut warnings);
+        out.extend(warnings.into_iter().map(|w| format!("task '{}': {}", task.name, w)));
+    }
+
+    out
+}
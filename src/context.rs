@@ -1,4 +1,5 @@
 use std::{
+    collections::BTreeMap,
     path::{Path, PathBuf},
     sync::{
         Arc, RwLock,
@@ -8,7 +9,9 @@ use type_map::concurrent::TypeMap;
 use crate::{
     prelude::*,
     cfg::Config,
-    op::WorkDir,
+    interpolation::{self, VarRef},
+    op::{ConfigDir, Env, Vars, WorkDir},
+    schedule::DependencyRuns,
 };
 
 
@@ -81,6 +84,8 @@ impl Context {
 
         let root_frame = Frame::root();
         root_frame.insert_var(WorkDir(root_path.into()));
+        root_frame.insert_var(ConfigDir(root_path.into()));
+        root_frame.insert_var(DependencyRuns::default());
 
         Ok(Self {
             config: Arc::new(config),
@@ -111,13 +116,78 @@ impl Context {
         self.get_closest_var::<WorkDir>().expect("bug: no root workdir").0
     }
 
+    /// Returns the value of the user defined variable with the given name from
+    /// the closest frame that defines it.
+    pub fn user_var(&self, name: &str) -> Option<String> {
+        self.frames().find_map(|f| f.get_var::<Vars>()?.values.get(name).cloned())
+    }
+
+    /// Returns the items of the user defined variable with the given name
+    /// from the closest frame that defines it, if it is a list. Returns `None`
+    /// if the variable is not defined or not a list.
+    pub fn user_var_list(&self, name: &str) -> Option<Vec<String>> {
+        let vars = self.frames()
+            .filter_map(|f| f.get_var::<Vars>())
+            .find(|vars| vars.values.contains_key(name))?;
+        vars.lists.get(name).cloned()
+    }
+
+    /// Sets the user defined variable in the top frame.
+    pub fn set_user_var(&self, name: impl Into<String>, value: impl Into<String>) {
+        let name = name.into();
+        let mut vars = self.top_frame.get_var::<Vars>().unwrap_or_default();
+        vars.lists.remove(&name);
+        vars.values.insert(name, value.into());
+        self.top_frame.insert_var(vars);
+    }
+
+    /// Sets the user defined variable to a list in the top frame. When
+    /// interpolated into a string, the items are joined by spaces.
+    pub fn set_user_var_list(&self, name: impl Into<String>, items: Vec<String>) {
+        let name = name.into();
+        let mut vars = self.top_frame.get_var::<Vars>().unwrap_or_default();
+        vars.values.insert(name.clone(), items.join(" "));
+        vars.lists.insert(name, items);
+        self.top_frame.insert_var(vars);
+    }
+
+    /// Replaces all variable references (e.g. `${name}` or `${env:NAME}`) in
+    /// `s` with their values from this context. See the `interpolation`
+    /// module for details.
+    pub fn interpolate(&self, s: &str) -> Result<String> {
+        let mut env = None;
+        interpolation::interpolate(s, |var| match var {
+            VarRef::User(name) => self.user_var(name),
+            VarRef::Env(name) => env.get_or_insert_with(|| self.env())
+                .get(name)
+                .cloned()
+                .or_else(|| std::env::var(name).ok()),
+        }).with_context(|| format!(
+            "failed to interpolate variables in '{}' (in task '{}')",
+            s,
+            self.frame_label(),
+        ))
+    }
+
+    /// Returns all environment variables set in the execution context. The
+    /// variables of all frames are merged, with variables of closer frames
+    /// overwriting the ones of frames further up the chain.
+    pub fn env(&self) -> BTreeMap<String, String> {
+        let frames = self.frames().collect::<Vec<_>>();
+        frames.into_iter()
+            .rev()
+            .filter_map(|f| f.get_var::<Env>())
+            .flat_map(|env| env.0)
+            .collect()
+    }
+
     /// Joins the `new_path` with the current workdir context. Three possible
     /// cases:
     /// - `new_path` is absolute: `new_path` is returned
     /// - `new_path` starts with `./`: the closest `WorkDir` variable joined
     ///   with `new_path` is returned.
-    /// - Else: the path of the config file (minus file name) joined with
-    ///   `new_path` is returned.
+    /// - Else: the directory of the config file defining the current task (see
+    ///   `ConfigDir`) joined with `new_path` is returned.
     pub fn join_workdir(&self, new_path: impl AsRef<Path>) -> PathBuf {
         let new_path = new_path.as_ref();
         match () {
@@ -126,7 +196,7 @@ impl Context {
                 self.workdir().join(new_path.strip_prefix(".").unwrap())
             }
             _ => {
-                let base = self.root_frame().get_var::<WorkDir>().expect("bug: no root workdir");
+                let base = self.get_closest_var::<ConfigDir>().expect("bug: no root config dir");
                 base.0.join(new_path)
             }
         }
@@ -0,0 +1,230 @@
+//! Locations in the configuration file and rendering errors with a code
+//! snippet pointing to that location, similar to rustc diagnostics.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    path::PathBuf,
+    sync::Arc,
+};
+use yaml_rust::{parser::{Event, Parser}, scanner::Marker};
+
+
+/// A loaded configuration file.
+pub struct SourceFile {
+    pub path: PathBuf,
+    pub content: String,
+}
+
+/// A position in a source file. Line and column are 1-based.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl From<Marker> for Position {
+    fn from(marker: Marker) -> Self {
+        // The column of `Marker` is 0-based.
+        Self { line: marker.line(), column: marker.col() + 1 }
+    }
+}
+
+/// A position in a specific source file.
+#[derive(Clone)]
+pub struct Location {
+    pub file: Arc<SourceFile>,
+    pub pos: Position,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file.path.display(), self.pos.line, self.pos.column)
+    }
+}
+
+impl fmt::Debug for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// A message with a location. When displayed, the relevant line of the source
+/// file is shown with a caret pointing to the location.
+#[derive(Debug)]
+pub struct Diagnostic {
+    message: String,
+    location: Location,
+}
+
+impl Diagnostic {
+    pub fn new(message: impl Into<String>, location: Location) -> Self {
+        Self { message: message.into(), location }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Position { line, column } = self.location.pos;
+        let gutter = " ".repeat(line.to_string().len());
+        let snippet = self.location.file.content.lines().nth(line.saturating_sub(1));
+
+        writeln!(f, "{}", self.message)?;
+        write!(f, "{}--> {}", gutter, self.location)?;
+        if let Some(snippet) = snippet {
+            // Tabs would mess up the position of the caret.
+            let snippet = snippet.replace('\t', " ");
+            writeln!(f)?;
+            writeln!(f, "{} |", gutter)?;
+            writeln!(f, "{} | {}", line, snippet)?;
+            write!(f, "{} | {:>2$}", gutter, "^", column)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+/// A value for an operation and for all operations nested in it, in the same
+/// order as `Operation::children`. Used for the positions of operations.
+#[derive(Debug, Clone)]
+pub struct OperationTree<T> {
+    pub value: T,
+    pub children: Vec<OperationTree<T>>,
+}
+
+impl<T> OperationTree<T> {
+    pub fn map<U>(self, f: &impl Fn(T) -> U) -> OperationTree<U> {
+        OperationTree {
+            value: f(self.value),
+            children: self.children.into_iter().map(|c| c.map(f)).collect(),
+        }
+    }
+
+    /// Returns the value of the operation at `path`, where each element is an
+    /// index into the list of operations of that level. If the nested
+    /// operation has no value, the one of the closest ancestor is returned.
+    pub fn find<'a>(trees: &'a [Self], path: &[usize]) -> Option<&'a T> {
+        let (first, rest) = path.split_first()?;
+        let tree = trees.get(*first)?;
+        Self::find(&tree.children, rest).or(Some(&tree.value))
+    }
+}
+
+/// Positions of a task definition in the source file.
+#[derive(Debug)]
+pub struct TaskPositions {
+    /// The task name.
+    pub key: Position,
+
+    /// The operations of the task, including nested ones.
+    pub operations: Vec<OperationTree<Position>>,
+}
+
+/// Finds the positions of all tasks and their operations in the given YAML
+/// document. This does not report errors: if the document is invalid, the
+/// positions found so far are returned.
+pub fn task_positions(content: &str) -> HashMap<String, TaskPositions> {
+    let mut parser = Parser::new(content.chars());
+    let mut events = Vec::new();
+    while let Ok((event, marker)) = parser.next() {
+        if event == Event::StreamEnd {
+            break;
+        }
+        events.push((event, marker));
+    }
+
+    let mut out = HashMap::new();
+    let mut i = match events.iter().position(|(e, _)| matches!(e, Event::MappingStart(_))) {
+        Some(i) => i + 1,
+        None => return out,
+    };
+
+    while let Some((Event::Scalar(name, ..), marker)) = events.get(i) {
+        i += 1;
+        let operations = match events.get(i) {
+            Some((Event::SequenceStart(_), _)) => sequence_items(&events, i),
+            Some((Event::MappingStart(_), _)) => {
+                // A task defined as map: find the `run` field.
+                run_items(&events, i)
+            }
+            _ => vec![],
+        };
+
+        out.insert(name.clone(), TaskPositions { key: (*marker).into(), operations });
+        i = skip_node(&events, i);
+    }
+
+    out
+}
+
+/// Returns the positions of the items of the `run` field of the mapping
+/// starting at `start`. Empty if there is no such field.
+fn run_items(events: &[(Event, Marker)], start: usize) -> Vec<OperationTree<Position>> {
+    let mut i = start + 1;
+    while let Some((Event::Scalar(key, ..), _)) = events.get(i) {
+        if key == "run" {
+            return sequence_items(events, i + 1);
+        }
+        i = skip_node(events, i + 1);
+    }
+
+    vec![]
+}
+
+/// Returns the positions of all items of the sequence starting at `start`,
+/// including the ones of nested operations. Operations are nested either
+/// directly in the value of the operation (e.g. `concurrently: [...]`) or in
+/// its `run` field (e.g. `watch: { run: [...] }`).
+fn sequence_items(events: &[(Event, Marker)], start: usize) -> Vec<OperationTree<Position>> {
+    let mut out = Vec::new();
+    if !matches!(events.get(start), Some((Event::SequenceStart(_), _))) {
+        return out;
+    }
+
+    let mut i = start + 1;
+    while let Some((event, marker)) = events.get(i) {
+        if *event == Event::SequenceEnd {
+            break;
+        }
+
+        // The marker of a block mapping points to the first `:`, but we want
+        // to point to the key.
+        let (marker, children) = match (event, events.get(i + 1)) {
+            (Event::MappingStart(_), Some((Event::Scalar(..), key_marker))) => {
+                let children = match events.get(i + 2) {
+                    Some((Event::SequenceStart(_), _)) => sequence_items(events, i + 2),
+                    Some((Event::MappingStart(_), _)) => run_items(events, i + 2),
+                    _ => vec![],
+                };
+                (key_marker, children)
+            }
+            _ => (marker, vec![]),
+        };
+        out.push(OperationTree { value: (*marker).into(), children });
+        i = skip_node(events, i);
+    }
+
+    out
+}
+
+/// Returns the index of the first event after the node starting at `start`.
+fn skip_node(events: &[(Event, Marker)], start: usize) -> usize {
+    let mut depth = 0;
+    let mut i = start;
+    while let Some((event, _)) = events.get(i) {
+        i += 1;
+        match event {
+            Event::SequenceStart(_) | Event::MappingStart(_) => depth += 1,
+            Event::SequenceEnd | Event::MappingEnd => depth -= 1,
+            _ => {}
+        }
+
+        if depth <= 0 {
+            break;
+        }
+    }
+
+    i
+}
@@ -0,0 +1,38 @@
+//! Durations in the configuration file.
+
+use std::{convert::TryFrom, fmt, time::Duration};
+use serde::Deserialize;
+
+
+/// Wrapper around `std::time::Duration` that nicely deserializes. Can be
+/// specified either as integer (milliseconds, like `debounce`) or as string
+/// with units, e.g. `"500ms"`, `"30s"` or `"1m 30s"`.
+#[derive(Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "RawDuration")]
+pub struct HumanDuration(pub Duration);
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawDuration {
+    Millis(u64),
+    Human(String),
+}
+
+impl TryFrom<RawDuration> for HumanDuration {
+    type Error = String;
+
+    fn try_from(src: RawDuration) -> Result<Self, Self::Error> {
+        match src {
+            RawDuration::Millis(ms) => Ok(Self(Duration::from_millis(ms))),
+            RawDuration::Human(s) => humantime::parse_duration(&s)
+                .map(Self)
+                .map_err(|e| format!("invalid duration '{}': {}", s, e)),
+        }
+    }
+}
+
+impl fmt::Debug for HumanDuration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&humantime::format_duration(self.0), f)
+    }
+}
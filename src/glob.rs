@@ -0,0 +1,153 @@
+//! Path patterns in the configuration, which can contain globs like
+//! `src/**/*.scss`.
+
+use std::{
+    fmt,
+    io,
+    path::{Component, Path, PathBuf},
+};
+use globset::{GlobBuilder, GlobMatcher};
+use crate::{interpolation, prelude::*};
+
+
+/// A path or glob pattern, resolved against the workdir of the context it was
+/// created in.
+#[derive(Debug, Clone)]
+pub struct PathPattern {
+    /// The longest prefix of the pattern without glob characters. For simple
+    /// paths, this is the whole path.
+    pub base: PathBuf,
+
+    /// Matches paths relative to `base`. `None` if the pattern does not
+    /// contain any glob characters.
+    glob: Option<GlobMatcher>,
+}
+
+impl PathPattern {
+    /// Interpolates the pattern and resolves it via `Context::join_workdir`.
+    pub fn new(pattern: &str, ctx: &Context) -> Result<Self> {
+        Self::resolve(pattern, ctx, |base| ctx.join_workdir(base))
+    }
+
+    /// Like `new`, but relative paths are always resolved against the
+    /// current workdir (`Context::workdir`).
+    pub fn in_workdir(pattern: &str, ctx: &Context) -> Result<Self> {
+        Self::resolve(pattern, ctx, |base| ctx.workdir().join(base))
+    }
+
+    fn resolve(
+        pattern: &str,
+        ctx: &Context,
+        resolve_base: impl FnOnce(&Path) -> PathBuf,
+    ) -> Result<Self> {
+        let pattern = ctx.interpolate(pattern)?;
+        let (base, glob) = parse(&pattern)?;
+        Ok(Self { base: resolve_base(&base), glob })
+    }
+
+    /// Checks the glob syntax of the pattern without resolving it. Variables
+    /// are not known yet, so they are treated like plain text. Used to report
+    /// errors when loading the configuration.
+    pub fn check_syntax(pattern: &str) -> Result<()> {
+        let pattern = interpolation::interpolate(pattern, |_| Some("_".into()))?;
+        parse(&pattern).map(|_| ())
+    }
+
+    /// Returns whether this pattern contains glob characters.
+    pub fn is_glob(&self) -> bool {
+        self.glob.is_some()
+    }
+
+    /// Returns whether the given (absolute) path matches this pattern. For
+    /// patterns without glob characters, all paths inside `base` match.
+    pub fn matches(&self, path: &Path) -> bool {
+        match (path.strip_prefix(&self.base), &self.glob) {
+            (Err(_), _) => false,
+            (Ok(_), None) => true,
+            (Ok(relative), Some(glob)) => glob.is_match(relative),
+        }
+    }
+
+    /// Returns all files matching this pattern, sorted. Patterns without glob
+    /// characters match the file itself or all files inside the directory.
+    /// Paths that don't exist result in no files. Symlinks to files are
+    /// included, but symlinks to directories are not followed, as they could
+    /// form cycles.
+    pub fn files(&self) -> io::Result<Vec<PathBuf>> {
+        fn walk(dir: &Path, out: &mut Vec<PathBuf>) -> io::Result<()> {
+            for entry in std::fs::read_dir(dir)? {
+                let entry = entry?;
+                let path = entry.path();
+
+                // `file_type` does not follow symlinks, `is_file` does.
+                let file_type = entry.file_type()?;
+                if file_type.is_dir() {
+                    walk(&path, out)?;
+                } else if file_type.is_file() || path.is_file() {
+                    out.push(path);
+                }
+            }
+            Ok(())
+        }
+
+        let mut out = Vec::new();
+        if self.base.is_dir() {
+            walk(&self.base, &mut out)?;
+        } else if self.base.exists() {
+            out.push(self.base.clone());
+        }
+
+        out.retain(|path| self.matches(path));
+        out.sort();
+        Ok(out)
+    }
+}
+
+impl fmt::Display for PathPattern {
+    /// Shows the resolved pattern, i.e. the base joined with the glob.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.glob {
+            None => write!(f, "{}", self.base.display()),
+            Some(glob) => write!(f, "{}", self.base.join(glob.glob().glob()).display()),
+        }
+    }
+}
+
+/// Returns the longest prefix of the pattern without glob characters, e.g.
+/// `src` for `src/**/*.scss`. For simple paths, this is the whole path.
+pub fn base_of(pattern: &str) -> PathBuf {
+    split(pattern).0
+}
+
+/// Splits the pattern into its base and the glob matching the rest, if any.
+fn parse(pattern: &str) -> Result<(PathBuf, Option<GlobMatcher>)> {
+    let (base, rest) = split(pattern);
+    if rest.components().next().is_none() {
+        return Ok((base, None));
+    }
+
+    // Like in shells, `*` does not match `/`, only `**` does.
+    let glob = GlobBuilder::new(&rest.to_string_lossy())
+        .literal_separator(true)
+        .build()
+        .context(format!("invalid glob pattern '{}'", pattern))?;
+    Ok((base, Some(glob.compile_matcher())))
+}
+
+/// Splits the pattern into the longest prefix without glob characters and the
+/// rest.
+fn split(pattern: &str) -> (PathBuf, PathBuf) {
+    let mut base = PathBuf::new();
+    let mut rest = PathBuf::new();
+    for component in Path::new(pattern).components() {
+        let is_glob = matches!(component, Component::Normal(s)
+            if s.to_string_lossy().contains(['*', '?', '[', '{']));
+        if is_glob || rest.components().next().is_some() {
+            rest.push(component);
+        } else {
+            base.push(component);
+        }
+    }
+
+    (base, rest)
+}
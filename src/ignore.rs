@@ -0,0 +1,228 @@
+//! Ignore rules for `watch`: patterns of the `ignore` property and of
+//! `.gitignore`/`.ignore` files. All patterns use the gitignore syntax.
+
+use std::{
+    fs,
+    io,
+    path::{Path, PathBuf},
+};
+use globset::{GlobBuilder, GlobMatcher};
+use crate::{interpolation, prelude::*};
+
+
+/// Files containing ignore rules, see `IgnoreRules::add_ignore_files`.
+const IGNORE_FILES: &[&str] = &[".gitignore", ".ignore"];
+
+#[derive(Debug, Clone)]
+struct Rule {
+    /// The directory the pattern is relative to.
+    base: PathBuf,
+    glob: GlobMatcher,
+
+    /// Patterns starting with `!` re-include paths ignored by earlier rules.
+    negated: bool,
+
+    /// Patterns ending with `/` only match directories.
+    dir_only: bool,
+}
+
+/// A list of ignore rules. Like in git, later rules take precedence over
+/// earlier ones.
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreRules {
+    rules: Vec<Rule>,
+}
+
+impl IgnoreRules {
+    /// Adds a single line in gitignore syntax, relative to `base`. Empty lines
+    /// and comments are skipped.
+    pub fn add(&mut self, base: &Path, line: &str) -> Result<()> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(());
+        }
+
+        let (negated, pattern) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let (dir_only, pattern) = match pattern.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, pattern),
+        };
+
+        // Patterns containing a slash are relative to `base`, all others match
+        // at any depth.
+        let glob = if pattern.contains('/') {
+            pattern.trim_start_matches('/').to_owned()
+        } else {
+            format!("**/{}", pattern)
+        };
+        let glob = GlobBuilder::new(&glob)
+            .literal_separator(true)
+            .build()
+            .context(format!("invalid ignore pattern '{}'", line))?
+            .compile_matcher();
+
+        self.rules.push(Rule { base: base.to_owned(), glob, negated, dir_only });
+        Ok(())
+    }
+
+    /// Checks the syntax of a single line without adding it. Variables are not
+    /// known yet, so they are treated like plain text. Used to report errors
+    /// when loading the configuration.
+    pub fn check_syntax(line: &str) -> Result<()> {
+        let line = interpolation::interpolate(line, |_| Some("_".into()))?;
+        Self::default().add(Path::new(""), &line)
+    }
+
+    /// Adds the rules of all ignore files that apply to `root`: the ones in
+    /// `root` and all its (not ignored) subdirectories, as well as the ones in
+    /// parent directories up to the root of the git repository. The `.git`
+    /// directory itself is always ignored.
+    pub fn add_ignore_files(&mut self, root: &Path) -> Result<()> {
+        let (dir, walk) = match root.parent() {
+            Some(parent) if !root.is_dir() => (parent, false),
+            _ => (root, true),
+        };
+        self.add(dir, ".git/")?;
+
+        // Rules of deeper files take precedence, so parents come first.
+        let repo_depth = dir.ancestors().position(|d| d.join(".git").exists()).unwrap_or(0);
+        let parents = dir.ancestors().skip(1).take(repo_depth).collect::<Vec<_>>();
+        for parent in parents.into_iter().rev() {
+            self.add_files_in(parent)?;
+        }
+
+        self.add_files_in(dir)?;
+        if walk {
+            self.walk(dir)?;
+        }
+
+        Ok(())
+    }
+
+    /// Adds the ignore files of all subdirectories of `dir` that are not
+    /// ignored.
+    fn walk(&mut self, dir: &Path) -> Result<()> {
+        let entries = fs::read_dir(dir)
+            .context(format!("failed to read directory '{}'", dir.display()))?;
+        for entry in entries {
+            let entry = entry?;
+            // `file_type` does not follow symlinks, which could form cycles.
+            if entry.file_type()?.is_dir() && !self.is_ignored(&entry.path()) {
+                self.add_files_in(&entry.path())?;
+                self.walk(&entry.path())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Adds the rules of the ignore files directly in `dir`, if they exist.
+    fn add_files_in(&mut self, dir: &Path) -> Result<()> {
+        for name in IGNORE_FILES {
+            let path = dir.join(name);
+            let content = match fs::read_to_string(&path) {
+                Ok(content) => content,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e).context(format!("failed to read '{}'", path.display())),
+            };
+
+            for line in content.lines() {
+                self.add(dir, line).context(format!("invalid line in '{}'", path.display()))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns whether the given (absolute) path is ignored. Like in git, paths
+    /// inside an ignored directory are always ignored. Paths that don't exist
+    /// (anymore) might have been directories, so rules only matching
+    /// directories apply to them, too. Otherwise, deleting an ignored
+    /// directory would not be ignored.
+    pub fn is_ignored(&self, path: &Path) -> bool {
+        if self.rules.is_empty() {
+            return false;
+        }
+
+        self.matches(path, path.is_dir() || !path.exists())
+            || path.ancestors().skip(1).any(|dir| self.matches(dir, true))
+    }
+
+    /// Returns whether the last rule matching `path` ignores it.
+    fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        let last_match = self.rules.iter()
+            .rev()
+            .filter(|rule| is_dir || !rule.dir_only)
+            .find(|rule| match path.strip_prefix(&rule.base) {
+                Ok(relative) => relative.components().next().is_some()
+                    && rule.glob.is_match(relative),
+                Err(_) => false,
+            });
+
+        matches!(last_match, Some(rule) if !rule.negated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Rules relative to the crate directory, so that existing files and
+    /// directories (`Cargo.toml`, `src`) can be used.
+    fn rules_in_crate(lines: &[&str]) -> (PathBuf, IgnoreRules) {
+        let base = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let mut rules = IgnoreRules::default();
+        for line in lines {
+            rules.add(&base, line).unwrap();
+        }
+        (base, rules)
+    }
+
+    #[test]
+    fn patterns() {
+        let (base, rules) = rules_in_crate(&["# comment", "", "*.log", "/target", "docs/*.md"]);
+        assert!(rules.is_ignored(&base.join("a.log")));
+        assert!(rules.is_ignored(&base.join("sub/dir/a.log")));
+        assert!(rules.is_ignored(&base.join("target")));
+        assert!(rules.is_ignored(&base.join("target/debug/floof")));
+        assert!(!rules.is_ignored(&base.join("sub/target")));
+        assert!(rules.is_ignored(&base.join("docs/file-format.md")));
+        assert!(!rules.is_ignored(&base.join("docs/sub/file-format.md")));
+        assert!(!rules.is_ignored(&base.join("README.md")));
+        assert!(!rules.is_ignored(&base));
+    }
+
+    #[test]
+    fn negation() {
+        let (base, rules) = rules_in_crate(&["*.log", "!keep.log"]);
+        assert!(rules.is_ignored(&base.join("a.log")));
+        assert!(!rules.is_ignored(&base.join("keep.log")));
+
+        let (base, rules) = rules_in_crate(&["!keep.log", "*.log"]);
+        assert!(rules.is_ignored(&base.join("keep.log")));
+    }
+
+    #[test]
+    fn dir_only() {
+        let (base, rules) = rules_in_crate(&["src/", "Cargo.toml/", "build/"]);
+        assert!(rules.is_ignored(&base.join("src")));
+        assert!(rules.is_ignored(&base.join("src/main.rs")));
+        assert!(!rules.is_ignored(&base.join("Cargo.toml")));
+
+        // Deleted paths might have been directories.
+        assert!(rules.is_ignored(&base.join("build")));
+        assert!(rules.is_ignored(&base.join("build/out.o")));
+        assert!(rules.is_ignored(&base.join("sub/build")));
+    }
+
+    #[test]
+    fn check_syntax() {
+        assert!(IgnoreRules::check_syntax("*.log").is_ok());
+        assert!(IgnoreRules::check_syntax("${dir}/*.o").is_ok());
+        assert!(IgnoreRules::check_syntax("[a").is_err());
+        assert!(IgnoreRules::check_syntax("${dir").is_err());
+    }
+}
@@ -0,0 +1,136 @@
+//! Variable interpolation in strings of the configuration.
+//!
+//! `${name}` is replaced by the value of the user defined variable `name` and
+//! `${env:NAME}` by the value of the environment variable `NAME`. To get a
+//! literal `$`, write `$$`. A `$` that is followed by neither `{` nor `$` is
+//! kept as is.
+
+use crate::prelude::*;
+
+
+/// A reference to a variable in an interpolated string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VarRef<'a> {
+    /// `${name}`
+    User(&'a str),
+    /// `${env:NAME}`
+    Env(&'a str),
+}
+
+/// Replaces all variable references in `s` with the value returned by
+/// `lookup`. If `lookup` returns `None`, an error is returned.
+pub fn interpolate(
+    s: &str,
+    mut lookup: impl FnMut(VarRef<'_>) -> Option<String>,
+) -> Result<String> {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(pos) = rest.find('$') {
+        out.push_str(&rest[..pos]);
+        let tail = &rest[pos + 1..];
+
+        if let Some(after) = tail.strip_prefix('$') {
+            out.push('$');
+            rest = after;
+        } else if let Some(after) = tail.strip_prefix('{') {
+            let end = after.find('}')
+                .ok_or_else(|| anyhow!("unterminated '${{' in '{}'", s))?;
+            let var = parse_ref(&after[..end])?;
+            let value = lookup(var).ok_or_else(|| match var {
+                VarRef::User(name) => anyhow!("unknown variable '{}'", name),
+                VarRef::Env(name) => anyhow!("environment variable '{}' is not set", name),
+            })?;
+
+            out.push_str(&value);
+            rest = &after[end + 1..];
+        } else {
+            out.push('$');
+            rest = tail;
+        }
+    }
+    out.push_str(rest);
+
+    Ok(out)
+}
+
+/// Returns the variable if `s` consists of exactly one variable reference, e.g.
+/// `${name}`.
+pub fn single_ref(s: &str) -> Option<VarRef<'_>> {
+    let inner = s.strip_prefix("${")?.strip_suffix('}')?;
+    if inner.contains('}') {
+        return None;
+    }
+
+    parse_ref(inner).ok()
+}
+
+fn parse_ref(inner: &str) -> Result<VarRef<'_>> {
+    let var = match inner.strip_prefix("env:") {
+        Some(name) => VarRef::Env(name.trim()),
+        None => VarRef::User(inner.trim()),
+    };
+
+    let name = match var {
+        VarRef::User(name) | VarRef::Env(name) => name,
+    };
+    if name.is_empty() {
+        bail!("empty variable name in '${{{}}}'", inner);
+    }
+
+    Ok(var)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(var: VarRef<'_>) -> Option<String> {
+        match var {
+            VarRef::User("name") => Some("peter".to_owned()),
+            VarRef::Env("HOME") => Some("/home/peter".to_owned()),
+            _ => None,
+        }
+    }
+
+    fn interp(s: &str) -> Result<String> {
+        interpolate(s, lookup)
+    }
+
+    #[test]
+    fn variables() {
+        assert_eq!(interp("hi ${name}!").unwrap(), "hi peter!");
+        assert_eq!(interp("${ name }").unwrap(), "peter");
+        assert_eq!(interp("${env:HOME}/x").unwrap(), "/home/peter/x");
+        assert_eq!(interp("${name}${env:HOME}").unwrap(), "peter/home/peter");
+    }
+
+    #[test]
+    fn dollars() {
+        assert_eq!(interp("$$").unwrap(), "$");
+        assert_eq!(interp("$${name}").unwrap(), "${name}");
+        assert_eq!(interp("$$$${name}").unwrap(), "$${name}");
+        assert_eq!(interp("$$${name}").unwrap(), "$peter");
+        assert_eq!(interp("$HOME and $").unwrap(), "$HOME and $");
+        assert_eq!(interp("a $ b").unwrap(), "a $ b");
+    }
+
+    #[test]
+    fn errors() {
+        assert!(interp("${foo}").unwrap_err().to_string().contains("unknown variable 'foo'"));
+        assert!(interp("${env:FOO}").unwrap_err().to_string().contains("'FOO' is not set"));
+        assert!(interp("x ${name").unwrap_err().to_string().contains("unterminated"));
+        assert!(interp("${ }").unwrap_err().to_string().contains("empty variable name"));
+        assert!(interp("${env:}").unwrap_err().to_string().contains("empty variable name"));
+    }
+
+    #[test]
+    fn single() {
+        assert_eq!(single_ref("${name}"), Some(VarRef::User("name")));
+        assert_eq!(single_ref("${env:HOME}"), Some(VarRef::Env("HOME")));
+        assert_eq!(single_ref("a${name}"), None);
+        assert_eq!(single_ref("${name}a"), None);
+        assert_eq!(single_ref("${a}${b}"), None);
+        assert_eq!(single_ref("${}"), None);
+        assert_eq!(single_ref("$$"), None);
+    }
+}
@@ -10,15 +10,23 @@ mod ui;
 mod task;
 mod args;
 mod cfg;
+mod check;
 mod op;
 mod prelude;
 mod context;
+mod diagnostic;
+mod duration;
+mod glob;
+mod ignore;
+mod interpolation;
+mod process;
+mod schedule;
 
 // We "reexport" some symbols here to make importing them (in other modules)
 // easier and to avoid `task::Task` paths.
 pub(crate) use crate::{
     args::Args,
-    task::Task,
+    task::{Task, TaskArgs},
     op::{Operation, Operations},
 };
 
@@ -30,40 +38,91 @@ async fn main() -> Result<()> {
 
     ui::init(&args)?;
 
+    // `check` reports all problems itself instead of failing on the first.
+    if let Some(args::Command::Check) = args.cmd {
+        let config = Config::load_unvalidated(args.config.as_deref())?;
+        let ctx = Context::new(config, args.config.as_deref())?;
+        std::process::exit(check::run(&ctx)?);
+    }
+
     // Load configuration (either from specified or default path).
     let config = Config::load(args.config.as_deref())?;
 
     if args.debug_config {
-        println!("{:#?}", config);
+        print!("{}", serde_yaml::to_string(&config.describe())?);
     }
 
     // Create the context that is given to various threads and other functions.
     let ctx = Context::new(config, args.config.as_deref())?;
+    for (name, value) in args.vars {
+        ctx.set_user_var(name, value);
+    }
+    ctx.top_frame.insert_var(schedule::Jobs::new(args.jobs));
+    ctx.top_frame.insert_var(op::ForcePolling(args.poll));
+
+    // Run the requested task until it finishes or floof is asked to shut
+    // down. In the latter case, the task is cancelled, which stops all its
+    // running processes.
+    let result = tokio::select! {
+        result = run(&ctx, args.cmd) => result,
+        res = process::shutdown_signal() => {
+            res.context("failed to listen for shutdown signals")?;
+            Ok(op::Outcome::Cancelled.to_exit_code())
+        }
+    };
+
+    // Give processes of cancelled operations the chance to stop gracefully.
+    process::wait_for_stopping().await;
+
+    std::process::exit(result?);
+}
 
-    // Start default task.
-    let exit_code = match args.cmd {
+/// Runs the tasks specified by the CLI command and returns the exit code.
+async fn run(ctx: &Context, cmd: Option<args::Command>) -> Result<i32> {
+    let (tasks, args) = match cmd {
         None => {
-            match ctx.config.tasks.get("default") {
-                Some(task) => task.run(&ctx).await?.to_exit_code(),
-                None => {
-                    eprintln!("No default task defined!");
-                    eprintln!("Either define the task 'default' in the configuration or \
-                        run `floof run <task>` to run a specific task");
-                    1
-                }
+            if !ctx.config.tasks.contains_key("default") {
+                eprintln!("No default task defined!");
+                eprintln!("Either define the task 'default' in the configuration or \
+                    run `floof run <task>` to run a specific task");
+                return Ok(1);
             }
+
+            (vec!["default".to_owned()], TaskArgs::new())
         }
-        Some(args::Command::Run { task }) => {
+        Some(args::Command::Run { tasks, params }) => {
             // Make sure that all task names exist before starting anything.
-            match ctx.config.tasks.get(&task) {
-                Some(task) => task.run(&ctx).await?.to_exit_code(),
-                None => {
-                    eprintln!("Task '{}' not defined in configuration!", task);
-                    1
-                }
+            if let Some(task) = tasks.iter().find(|t| !ctx.config.tasks.contains_key(*t)) {
+                eprintln!("Task '{}' not defined in configuration!", task);
+                return Ok(1);
+            }
+
+            let args = params.into_iter().collect::<TaskArgs>();
+            let takes_param = |param: &String| {
+                tasks.iter().any(|t| ctx.config.tasks[t].params.contains_key(param))
+            };
+            if let Some(param) = args.keys().find(|p| !takes_param(p)) {
+                eprintln!("None of the specified tasks has a parameter '{}'!", param);
+                return Ok(1);
             }
+
+            (tasks, args)
         }
+        Some(args::Command::List) => {
+            ui::print_task_list(&ctx.config)?;
+            return Ok(0);
+        }
+        Some(args::Command::Check) => unreachable!("handled before loading the config"),
     };
 
-    std::process::exit(exit_code);
+    // Make sure that all tasks get valid arguments before starting anything.
+    for name in &tasks {
+        let task = &ctx.config.tasks[name];
+        if let Err(e) = task.check_args(&schedule::args_for(task, &args)) {
+            eprintln!("{:#}", e);
+            return Ok(1);
+        }
+    }
+
+    Ok(schedule::run_tasks(ctx, &tasks, &args).await?.to_exit_code())
 }
@@ -0,0 +1,242 @@
+//! The `cached` operation and the state file it uses to remember the inputs
+//! and outputs of previous runs.
+
+use std::{
+    collections::BTreeMap,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+use crate::{
+    Context,
+    glob::PathPattern,
+    prelude::*,
+};
+use super::{
+    Operation, Operations, Outcome, ParentKind, WorkDir,
+    describe_all, fields, tagged, validate_all,
+};
+
+
+/// Path of the state file, relative to the directory of the configuration
+/// file.
+const CACHE_FILE: &str = ".floof/cache";
+
+/// Recorded instead of a hash for patterns that do not match any file.
+const NO_FILES: &str = "no files";
+
+/// Guards the state file, as `cached` operations might run concurrently.
+static CACHE_LOCK: Lazy<tokio::sync::Mutex<()>> = Lazy::new(Default::default);
+
+/// Operation `cached`: runs operations only if the content of the input files
+/// changed (or the output files changed or are missing) since the last
+/// successful run. Hashes of all files are stored in `.floof/cache`.
+///
+/// Like `timeout`, this is a transparent wrapper: the operations are executed
+/// in the same context as the `cached` operation itself.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Cached {
+    inputs: Vec<String>,
+    #[serde(default)]
+    outputs: Vec<String>,
+    run: Operations,
+}
+
+impl Cached {
+    pub const KEYWORD: &'static str = "cached";
+}
+
+#[async_trait::async_trait]
+impl Operation for Cached {
+    fn keyword(&self) -> &'static str {
+        Self::KEYWORD
+    }
+
+    fn dyn_clone(&self) -> Box<dyn Operation> {
+        Box::new(self.clone())
+    }
+
+    fn describe(&self) -> Value {
+        tagged(Self::KEYWORD, fields([
+            ("inputs", self.inputs.clone().into()),
+            ("outputs", if self.outputs.is_empty() { Value::Null } else { self.outputs.clone().into() }),
+            ("run", describe_all(&self.run)),
+        ]))
+    }
+
+    fn children(&self) -> &[Box<dyn Operation>] {
+        &self.run
+    }
+
+    fn children_mut(&mut self) -> &mut [Box<dyn Operation>] {
+        &mut self.run
+    }
+
+    async fn run(&self, ctx: &Context) -> Result<Outcome> {
+        let inputs = resolve(&self.inputs, ctx)?;
+        let outputs = resolve(&self.outputs, ctx)?;
+        let cache_file = ctx.root_frame()
+            .get_var::<WorkDir>()
+            .expect("bug: no root workdir")
+            .0
+            .join(CACHE_FILE);
+
+        // The key identifies this operation in the state file. The resolved
+        // patterns are used, so that runs with different variables (e.g. task
+        // arguments) have separate entries.
+        let join = |patterns: &[PathPattern]| {
+            patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(", ")
+        };
+        let key = format!("{}: {} -> {}", ctx.frame_label(), join(&inputs), join(&outputs));
+
+        let current = hash_all(inputs.clone(), outputs.clone()).await?;
+        let all_outputs_exist = current.outputs.is_some();
+        if all_outputs_exist {
+            let _lock = CACHE_LOCK.lock().await;
+            if load(&cache_file)?.get(&key) == Some(&current) {
+                msg!(info [ctx]["cached"] "inputs and outputs unchanged → skipping");
+                return Ok(Outcome::Success);
+            }
+        }
+
+        for op in &self.run {
+            let outcome = op.run(ctx).await?;
+            if !outcome.is_success() {
+                return Ok(outcome);
+            }
+        }
+
+        // Hash again, as the operations likely changed the outputs. Inputs
+        // are hashed again as they might have been changed while running.
+        let entry = hash_all(inputs, outputs).await?;
+        if entry.outputs.is_none() {
+            msg!(warn [ctx]["cached"] "not all outputs exist after running → not caching");
+            return Ok(Outcome::Success);
+        }
+
+        let _lock = CACHE_LOCK.lock().await;
+        let mut cache = load(&cache_file)?;
+        cache.insert(key, entry);
+        save(&cache_file, &cache)?;
+
+        Ok(Outcome::Success)
+    }
+
+    fn validate(&self, parent: ParentKind<'_>, config: &Config) -> Vec<Error> {
+        let mut errors = Vec::new();
+        if self.inputs.is_empty() {
+            errors.push(anyhow!("`inputs` of `cached` operation must not be empty"));
+        }
+        errors.extend(
+            self.inputs.iter()
+                .chain(&self.outputs)
+                .filter_map(|pattern| PathPattern::check_syntax(pattern).err())
+        );
+
+        errors.extend(validate_all(&self.run, parent, config));
+        errors
+    }
+}
+
+/// The state of the inputs and outputs of one `cached` operation.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct Entry {
+    /// Hashes of all input files. Input patterns that do not match any file
+    /// are recorded with their base path and `NO_FILES`.
+    inputs: BTreeMap<PathBuf, String>,
+
+    /// Hashes of all output files. `None` if some output pattern does not
+    /// match any file.
+    outputs: Option<BTreeMap<PathBuf, String>>,
+}
+
+type Cache = BTreeMap<String, Entry>;
+
+fn resolve(patterns: &[String], ctx: &Context) -> Result<Vec<PathPattern>> {
+    patterns.iter().map(|p| PathPattern::new(p, ctx)).collect()
+}
+
+async fn hash_all(inputs: Vec<PathPattern>, outputs: Vec<PathPattern>) -> Result<Entry> {
+    /// Hashes the files of all patterns. Returns the hashes and whether all
+    /// patterns matched at least one file. Unmatched patterns are recorded
+    /// with a `NO_FILES` entry.
+    fn hash_files(patterns: &[PathPattern]) -> io::Result<(BTreeMap<PathBuf, String>, bool)> {
+        let mut out = BTreeMap::new();
+        let mut all_matched = true;
+        for pattern in patterns {
+            let files = pattern.files()?;
+            if files.is_empty() {
+                all_matched = false;
+                out.insert(pattern.base.clone(), NO_FILES.to_owned());
+            }
+            for file in files {
+                let hash = hash_file(&file)?;
+                out.insert(file, hash);
+            }
+        }
+
+        Ok((out, all_matched))
+    }
+
+    // Reading all files might take a while, so we don't block the executor.
+    tokio::task::spawn_blocking(move || -> io::Result<Entry> {
+        let (inputs, _) = hash_files(&inputs)?;
+        let outputs = match hash_files(&outputs)? {
+            (outputs, true) => Some(outputs),
+            (_, false) => None,
+        };
+        Ok(Entry { inputs, outputs })
+    })
+        .await
+        .expect("bug: hashing thread panicked")
+        .context("failed to hash inputs or outputs of `cached` operation")
+}
+
+/// Hashes the content of the file with 64 bit FNV-1a. The hash has to be
+/// stable across floof builds, as it's stored in the state file. It's not
+/// cryptographically secure, but that's not required to detect changes.
+fn hash_file(path: &Path) -> io::Result<String> {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+
+    let mut hash = OFFSET_BASIS;
+    let mut file = std::fs::File::open(path)?;
+    let mut buf = [0; 8 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        for &byte in &buf[..n] {
+            hash = (hash ^ u64::from(byte)).wrapping_mul(PRIME);
+        }
+    }
+
+    Ok(format!("{:016x}", hash))
+}
+
+fn load(path: &Path) -> Result<Cache> {
+    match std::fs::read(path) {
+        Ok(bytes) => {
+            // A broken state file is not a problem: everything just runs again.
+            Ok(serde_yaml::from_slice(&bytes).unwrap_or_default())
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Cache::new()),
+        Err(e) => Err(e).context(format!("failed to read '{}'", path.display())),
+    }
+}
+
+fn save(path: &Path, cache: &Cache) -> Result<()> {
+    let write = || -> Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_yaml::to_string(cache)?)?;
+        Ok(())
+    };
+
+    write().context(format!("failed to write '{}'", path.display()))
+}
@@ -1,20 +1,73 @@
 use std::{
     fmt,
     convert::TryFrom,
+    process::Stdio,
+    time::Duration,
 };
 use serde::Deserialize;
+use serde_yaml::{Mapping, Value};
+use tokio::io::{AsyncRead, AsyncReadExt};
 use crate::{
     Context,
+    duration::HumanDuration,
+    interpolation::{self, VarRef},
     prelude::*,
+    process::{self, Process, Signal, StopBehavior, Stopping},
 };
-use super::{Operation, Outcome};
+use super::{EnvMap, Operation, Outcome, ParentKind, fields, optional, tagged};
+
+/// How long to continue reading captured output after the process exited.
+const OUTPUT_TIMEOUT: Duration = Duration::from_millis(200);
 
 #[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
 pub struct Command {
     run: ProgramAndArgs,
 
     /// What working directory to execute the command in.
     workdir: Option<String>,
+
+    /// Additional environment variables set for the command.
+    #[serde(default)]
+    env: EnvMap,
+
+    /// If `true`, the command does not inherit any environment variables from
+    /// floof. Only the variables in `env` are set then.
+    #[serde(default)]
+    env_clear: bool,
+
+    /// Environment variables that are removed from the environment the
+    /// command inherits.
+    #[serde(default)]
+    env_remove: Vec<String>,
+
+    /// If set, the command is executed in a shell instead of directly.
+    shell: Option<Shell>,
+
+    /// The signal sent to the process when the command is cancelled. If not
+    /// set, the process is killed immediately.
+    stop_signal: Option<Signal>,
+
+    /// How long to wait for the process to exit after sending `stop_signal`
+    /// before killing it.
+    stop_timeout: Option<HumanDuration>,
+
+    /// If the process does not finish within this duration, it is stopped
+    /// and the command fails.
+    timeout: Option<HumanDuration>,
+
+    /// If set, the output of the command is stored in a variable instead of
+    /// being printed.
+    capture: Option<Capture>,
+
+    /// If `true`, the command is not started in its own process group, so
+    /// that it can read from the terminal.
+    #[serde(default)]
+    interactive: bool,
+
+    /// Processes started by this command that are currently being stopped.
+    #[serde(skip)]
+    stopping: Stopping,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -25,6 +78,9 @@ struct ProgramAndArgs {
 
     /// Arguments for the command.
     args: Vec<String>,
+
+    /// The original string, if the command was specified as single string.
+    source: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -48,7 +104,7 @@ impl TryFrom<RawProgramAndArgs> for ProgramAndArgs {
                 let program = split.next().unwrap().to_owned(); // checked above
                 let args: Vec<_> = split.map(|s| s.to_owned()).collect();
 
-                Ok(Self { program, args })
+                Ok(Self { program, args, source: Some(s) })
             }
             RawProgramAndArgs::Explicit(v) => {
                 if v.is_empty() {
@@ -62,12 +118,41 @@ impl TryFrom<RawProgramAndArgs> for ProgramAndArgs {
                 let program = v[0].clone();
                 let args = v.into_iter().skip(1).collect();
 
-                Ok(Self { program, args })
+                Ok(Self { program, args, source: None })
             }
         }
     }
 }
 
+impl ProgramAndArgs {
+    /// Returns a copy with all variables (e.g. `${name}`) interpolated. If the
+    /// command was specified as single string, interpolation happens before
+    /// splitting it. That way, a variable can expand to multiple arguments.
+    /// With the array syntax, an element consisting only of a list-valued
+    /// variable (e.g. `${changed_files}`) expands to one argument per item.
+    fn interpolate(&self, ctx: &Context) -> Result<Self> {
+        let raw = match &self.source {
+            Some(source) => RawProgramAndArgs::Simple(ctx.interpolate(source)?),
+            None => {
+                let mut fragments = Vec::new();
+                for fragment in std::iter::once(&self.program).chain(&self.args) {
+                    let list = match interpolation::single_ref(fragment) {
+                        Some(VarRef::User(name)) => ctx.user_var_list(name),
+                        _ => None,
+                    };
+                    match list {
+                        Some(items) => fragments.extend(items),
+                        None => fragments.push(ctx.interpolate(fragment)?),
+                    }
+                }
+                RawProgramAndArgs::Explicit(fragments)
+            }
+        };
+
+        Self::try_from(raw).map_err(|e| anyhow!("invalid command after interpolation: {}", e))
+    }
+}
+
 impl fmt::Display for ProgramAndArgs {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let print = |f: &mut fmt::Formatter, s: &str| {
@@ -89,11 +174,117 @@ impl fmt::Display for ProgramAndArgs {
     }
 }
 
+/// Specifies how the output of a command is captured.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(from = "RawCapture")]
+struct Capture {
+    /// Name of the variable to store the output in.
+    var: String,
+
+    /// Whether to remove leading and trailing whitespace from the output.
+    trim: bool,
+
+    /// Whether to capture stderr as well. It is appended to stdout.
+    stderr: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawCapture {
+    Simple(String),
+    Explicit(ExplicitCapture),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ExplicitCapture {
+    var: String,
+    #[serde(default = "default_trim")]
+    trim: bool,
+    #[serde(default)]
+    stderr: bool,
+}
+
+fn default_trim() -> bool {
+    true
+}
+
+impl Capture {
+    fn describe(&self) -> Value {
+        if self.trim && !self.stderr {
+            self.var.clone().into()
+        } else {
+            fields([
+                ("var", self.var.clone().into()),
+                ("trim", self.trim.into()),
+                ("stderr", self.stderr.into()),
+            ])
+        }
+    }
+}
+
+impl From<RawCapture> for Capture {
+    fn from(src: RawCapture) -> Self {
+        match src {
+            RawCapture::Simple(var) => Self { var, trim: true, stderr: false },
+            RawCapture::Explicit(e) => Self { var: e.var, trim: e.trim, stderr: e.stderr },
+        }
+    }
+}
+
+/// Specifies the shell in which a command is executed.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum Shell {
+    /// `true` to use the default shell, `false` to not use a shell at all.
+    Enabled(bool),
+
+    /// Name or path of the shell, which is invoked with `-c`.
+    Program(String),
+
+    /// The shell program and all arguments that are passed before the command
+    /// string, e.g. `["bash", "-e", "-c"]`.
+    Explicit(Vec<String>),
+}
+
+impl Shell {
+    /// Returns the program and arguments to invoke the shell with or `None` if
+    /// shell mode is disabled. The command string has to be appended to the
+    /// arguments.
+    fn invocation(&self) -> Option<(String, Vec<String>)> {
+        match self {
+            Self::Enabled(false) => None,
+            Self::Enabled(true) if cfg!(windows) => Some(("cmd".into(), vec!["/C".into()])),
+            Self::Enabled(true) => Some(("sh".into(), vec!["-c".into()])),
+            Self::Program(program) => Some((program.clone(), vec!["-c".into()])),
+            Self::Explicit(v) => Some((v[0].clone(), v[1..].to_vec())),
+        }
+    }
+
+    fn describe(&self) -> Value {
+        match self {
+            Self::Enabled(enabled) => (*enabled).into(),
+            Self::Program(program) => program.clone().into(),
+            Self::Explicit(v) => v.clone().into(),
+        }
+    }
+}
+
 impl From<ProgramAndArgs> for Command {
     fn from(src: ProgramAndArgs) -> Self {
         Self {
             run: src,
             workdir: None,
+            env: EnvMap::default(),
+            env_clear: false,
+            env_remove: vec![],
+            shell: None,
+            stop_signal: None,
+            stop_timeout: None,
+            timeout: None,
+            capture: None,
+            interactive: false,
+            stopping: Stopping::default(),
         }
     }
 }
@@ -121,40 +312,211 @@ impl Operation for Command {
         Box::new(self.clone())
     }
 
+    fn describe(&self) -> Value {
+        let run: Value = match &self.run.source {
+            Some(source) => source.clone().into(),
+            None => std::iter::once(&self.run.program).chain(&self.run.args).cloned().collect(),
+        };
+        let duration = |d: &Option<HumanDuration>| optional(d.as_ref().map(|d| format!("{:?}", d)));
+        let props = fields([
+            ("run", run.clone()),
+            ("workdir", optional(self.workdir.clone())),
+            ("env", if self.env.0.is_empty() { Value::Null } else { (&self.env).into() }),
+            ("env-clear", if self.env_clear { true.into() } else { Value::Null }),
+            ("env-remove", if self.env_remove.is_empty() {
+                Value::Null
+            } else {
+                self.env_remove.clone().into()
+            }),
+            ("shell", optional(self.shell.as_ref().map(Shell::describe))),
+            ("stop-signal", optional(self.stop_signal.map(|s| s.to_string()))),
+            ("stop-timeout", duration(&self.stop_timeout)),
+            ("timeout", duration(&self.timeout)),
+            ("capture", optional(self.capture.as_ref().map(Capture::describe))),
+            ("interactive", if self.interactive { true.into() } else { Value::Null }),
+        ]);
+
+        // Use the short form (just the command) if possible.
+        match props.as_mapping().map(Mapping::len) {
+            Some(1) => run,
+            _ => tagged(Self::KEYWORD, props),
+        }
+    }
+
     async fn run(&self, ctx: &Context) -> Result<Outcome> {
-        msg!(run [ctx]["command"] "running: {[green]}", self.run);
+        // Shell strings are not interpolated: the shell has its own syntax for
+        // `$`, and values might contain characters special to the shell.
+        let shell = self.shell.as_ref().and_then(Shell::invocation);
+        let run = match shell {
+            Some(_) => self.run.clone(),
+            None => self.run.interpolate(ctx)?,
+        };
 
-        let mut command = tokio::process::Command::new(&self.run.program);
-        command.kill_on_drop(true);
-        command.args(&self.run.args);
+        let mut command = match &shell {
+            None => {
+                msg!(run [ctx]["command"] "running: {[green]}", run);
+                let mut command = std::process::Command::new(&run.program);
+                command.args(&run.args);
+                command
+            }
+            Some((program, args)) => {
+                // Checked in `validate`.
+                let source = run.source.as_ref().expect("bug: shell command without string");
+                msg!(run [ctx]["command"] "running in {}: {[green]}", program, source);
+                let mut command = std::process::Command::new(program);
+                command.args(args);
+                command.arg(source);
+                command
+            }
+        };
         command.current_dir(match &self.workdir {
-            Some(workdir) => ctx.join_workdir(&workdir),
+            Some(workdir) => ctx.join_workdir(ctx.interpolate(workdir)?),
             None => ctx.workdir(),
         });
 
+        // Setup environment. The order is important here, as `env_clear`
+        // also removes all variables previously set. Variables from the
+        // execution context (`set-env`) are treated like inherited ones.
+        if self.env_clear {
+            command.env_clear();
+        }
+        command.envs(ctx.env());
+        for key in &self.env_remove {
+            command.env_remove(key);
+        }
+        for (key, value) in &self.env.0 {
+            command.env(key, ctx.interpolate(value)?);
+        }
+
+        if let Some(capture) = &self.capture {
+            command.stdout(Stdio::piped());
+            if capture.stderr {
+                command.stderr(Stdio::piped());
+            }
+        }
+
+        // If processes of previous, cancelled runs of this command are still
+        // stopping, we wait for them. Otherwise they might still hold
+        // resources (e.g. ports) that the new process needs.
+        if self.stopping.any() {
+            verbose!(waiting [ctx]["command"] "waiting for cancelled processes to stop...");
+            self.stopping.wait().await;
+        }
+
         // Start the command and return a descriptive error if that failed.
-        let mut child = command.spawn().map_err(|e| {
-            let mut context = format!("failed to spawn `{}`", self.run);
+        let stop = StopBehavior {
+            signal: self.stop_signal,
+            timeout: self.stop_timeout.map_or(process::DEFAULT_STOP_TIMEOUT, |d| d.0),
+            group: !self.interactive,
+        };
+        let mut process = Process::spawn(command, stop, self.stopping.clone()).map_err(|e| {
+            let mut context = format!("failed to spawn `{}`", run);
             if e.kind() == std::io::ErrorKind::NotFound {
                 context += &format!(
                     " (you probably don't have the command '{}' installed)",
-                    self.run.program,
+                    shell.as_ref().map_or(&run.program, |(program, _)| program),
                 );
             }
 
             anyhow::Error::from(e).context(context)
         })?;
 
-        // Check if the process has finished
-        let status = child.wait().await.context("error running process")?;
+        // Check if the process has finished. If this future is dropped (i.e.
+        // the operation is cancelled), `process` stops the process. The
+        // output has to be read while waiting, as the process might block
+        // when the pipe is full. Processes started in the background by the
+        // command might keep the pipes open, so after the process exited, we
+        // only read what is left in the pipes.
+        let stdout = process.take_stdout();
+        let stderr = process.take_stderr();
+        let mut output = Vec::new();
+        let mut error_output = Vec::new();
+        let finished = async {
+            let reading = async {
+                tokio::try_join!(read_all(stdout, &mut output), read_all(stderr, &mut error_output))
+            };
+            tokio::pin!(reading);
+
+            let mut read_result = None;
+            let status = loop {
+                tokio::select! {
+                    status = process.wait() => break status?,
+                    result = &mut reading, if read_result.is_none() => read_result = Some(result),
+                }
+            };
+            match read_result {
+                Some(result) => { result?; }
+                None => match tokio::time::timeout(OUTPUT_TIMEOUT, reading).await {
+                    Ok(result) => { result?; }
+                    Err(_) => {
+                        verbose!(- [ctx]["command"]
+                            "output still open after process exited (background process?), \
+                                not reading further");
+                    }
+                },
+            }
+            Ok::<_, std::io::Error>(status)
+        };
+        let result = match self.timeout {
+            None => finished.await,
+            Some(timeout) => match tokio::time::timeout(timeout.0, finished).await {
+                Ok(result) => result,
+                Err(_) => {
+                    msg!(warn [ctx]["command"]
+                        "{[green]} did not finish within {:?} → stopping it",
+                        run,
+                        timeout,
+                    );
+                    return Ok(Outcome::Failure);
+                }
+            },
+        };
+        let status = result.context("error running process")?;
         if status.success() {
+            if let Some(capture) = &self.capture {
+                output.extend(error_output);
+                let output = String::from_utf8_lossy(&output);
+                let value = if capture.trim { output.trim() } else { &output };
+                verbose!(- [ctx]["command"] "captured output into {[cyan]}", capture.var);
+                ctx.set_user_var(&capture.var, value);
+            }
+
             Ok(Outcome::Success)
         } else {
             msg!(warn [ctx]["command"]
                 "{[green]} returned non-zero exit code",
-                self.run,
+                run,
             );
             Ok(Outcome::Failure)
         }
     }
+
+    fn validate(&self, _parent: ParentKind<'_>, _config: &Config) -> Vec<Error> {
+        let mut errors = Vec::new();
+        match &self.shell {
+            Some(Shell::Explicit(v)) if v.is_empty() => {
+                errors.push(anyhow!("empty list as shell specification"));
+            }
+            Some(shell) if shell.invocation().is_some() && self.run.source.is_none() => {
+                errors.push(anyhow!("`run` has to be a single string when `shell` is used"));
+            }
+            _ => {}
+        }
+
+        if self.stop_timeout.is_some() && self.stop_signal.is_none() {
+            errors.push(anyhow!("`stop-timeout` specified without `stop-signal`"));
+        }
+
+        errors
+    }
+}
+
+/// Reads everything from the given pipe. Returns an empty buffer if there is
+/// no pipe. If this is cancelled, `buf` contains everything read so far.
+async fn read_all(pipe: Option<impl AsyncRead + Unpin>, buf: &mut Vec<u8>) -> std::io::Result<()> {
+    if let Some(mut pipe) = pipe {
+        pipe.read_to_end(buf).await?;
+    }
+
+    Ok(())
 }
@@ -1,9 +1,10 @@
 use serde::Deserialize;
+use serde_yaml::Value;
 use crate::{
     Context,
     prelude::*,
 };
-use super::{Operation, Operations, Outcome};
+use super::{Operation, Operations, Outcome, ParentKind, describe_all, tagged, validate_all};
 
 #[derive(Debug, Clone, Deserialize)]
 #[serde(deny_unknown_fields)]
@@ -23,6 +24,18 @@ impl Operation for Concurrently {
         Box::new(self.clone())
     }
 
+    fn describe(&self) -> Value {
+        tagged(Self::KEYWORD, describe_all(&self.0))
+    }
+
+    fn children(&self) -> &[Box<dyn Operation>] {
+        &self.0
+    }
+
+    fn children_mut(&mut self) -> &mut [Box<dyn Operation>] {
+        &mut self.0
+    }
+
     async fn run(&self, ctx: &Context) -> Result<Outcome> {
         let op_ctx = ctx.fork_op(Self::KEYWORD);
 
@@ -42,4 +55,8 @@ impl Operation for Concurrently {
 
         Ok(Outcome::Success)
     }
+
+    fn validate(&self, _parent: ParentKind<'_>, config: &Config) -> Vec<Error> {
+        validate_all(&self.0, ParentKind::Operation(Self::KEYWORD), config)
+    }
 }
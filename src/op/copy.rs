@@ -1,9 +1,10 @@
 use serde::Deserialize;
+use serde_yaml::Value;
 use crate::{
     Context,
     prelude::*,
 };
-use super::{Operation, Outcome};
+use super::{Operation, Outcome, fields, tagged};
 
 #[derive(Debug, Clone, Deserialize)]
 #[serde(deny_unknown_fields)]
@@ -26,8 +27,17 @@ impl Operation for Copy {
         Box::new(self.clone())
     }
 
-    async fn run(&self, _ctx: &Context) -> Result<Outcome> {
-        tokio::fs::copy(&self.src, &self.dst).await?;
+    fn describe(&self) -> Value {
+        tagged(Self::KEYWORD, fields([
+            ("src", self.src.clone().into()),
+            ("dst", self.dst.clone().into()),
+        ]))
+    }
+
+    async fn run(&self, ctx: &Context) -> Result<Outcome> {
+        let src = ctx.interpolate(&self.src)?;
+        let dst = ctx.interpolate(&self.dst)?;
+        tokio::fs::copy(&src, &dst).await?;
         Ok(Outcome::Success)
     }
 }
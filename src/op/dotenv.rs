@@ -0,0 +1,303 @@
+//! Loading environment variables from `.env` files. Defines the `dotenv`
+//! operation.
+
+use std::collections::BTreeMap;
+use serde::Deserialize;
+use serde_yaml::Value;
+use crate::{
+    Context,
+    prelude::*,
+};
+use super::{Env, Operation, Outcome, fields, tagged};
+
+
+/// Operation `dotenv`: loads one or more `.env` files and stores the variables
+/// in the current context, just like `set-env` does.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(from = "RawDotenv")]
+pub struct Dotenv {
+    files: Vec<String>,
+
+    /// Whether variables that are already set (in the execution context or in
+    /// floof's environment) are overwritten.
+    override_existing: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawDotenv {
+    Single(String),
+    Multiple(Vec<String>),
+    Explicit(ExplicitDotenv),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ExplicitDotenv {
+    files: Vec<String>,
+    #[serde(rename = "override", default = "default_override")]
+    override_existing: bool,
+}
+
+fn default_override() -> bool {
+    true
+}
+
+impl From<RawDotenv> for Dotenv {
+    fn from(src: RawDotenv) -> Self {
+        match src {
+            RawDotenv::Single(file) => Self { files: vec![file], override_existing: true },
+            RawDotenv::Multiple(files) => Self { files, override_existing: true },
+            RawDotenv::Explicit(e) => Self {
+                files: e.files,
+                override_existing: e.override_existing,
+            },
+        }
+    }
+}
+
+impl Dotenv {
+    pub const KEYWORD: &'static str = "dotenv";
+}
+
+#[async_trait::async_trait]
+impl Operation for Dotenv {
+    fn keyword(&self) -> &'static str {
+        Self::KEYWORD
+    }
+
+    fn dyn_clone(&self) -> Box<dyn Operation> {
+        Box::new(self.clone())
+    }
+
+    fn describe(&self) -> Value {
+        let value = match (&*self.files, self.override_existing) {
+            ([file], true) => file.clone().into(),
+            (_, true) => self.files.clone().into(),
+            (_, false) => fields([
+                ("files", self.files.clone().into()),
+                ("override", false.into()),
+            ]),
+        };
+
+        tagged(Self::KEYWORD, value)
+    }
+
+    async fn run(&self, ctx: &Context) -> Result<Outcome> {
+        let existing = ctx.env();
+        let mut env = ctx.top_frame.get_var::<Env>().unwrap_or_default();
+
+        for file in &self.files {
+            let path = ctx.join_workdir(file);
+            let content = tokio::fs::read_to_string(&path).await
+                .context(format!("failed to read '{}'", path.display()))?;
+
+            // Variables are expanded with the ones loaded so far, falling back
+            // to the context and then floof's environment.
+            let lookup = |name: &str| {
+                env.0.get(name)
+                    .or_else(|| existing.get(name))
+                    .cloned()
+                    .or_else(|| std::env::var(name).ok())
+            };
+            let vars = parse(&content, lookup)
+                .context(format!("failed to parse '{}'", path.display()))?;
+
+            for (key, value) in vars {
+                // This includes variables loaded from previous files.
+                let already_set = env.0.contains_key(&key)
+                    || existing.contains_key(&key)
+                    || std::env::var_os(&key).is_some();
+                if !self.override_existing && already_set {
+                    verbose!(- [ctx]["dotenv"] "{[cyan]} is already set, not overriding", key);
+                    continue;
+                }
+
+                env.0.insert(key, value);
+            }
+
+            verbose!(- [ctx]["dotenv"] "loaded {[cyan]}", path.display());
+        }
+
+        ctx.top_frame.insert_var(env);
+
+        Ok(Outcome::Success)
+    }
+}
+
+/// Parses the content of a `.env` file. Each non-empty line that is not a
+/// comment has the form `KEY=VALUE`, optionally prefixed with `export`. Values
+/// can be unquoted, single quoted (taken literally) or double quoted (escape
+/// sequences are interpreted). In unquoted and double quoted values, `${VAR}`
+/// is replaced by the value of `VAR` (as returned by `lookup`; empty if
+/// unset). Variables defined earlier in the file can be referenced as well.
+fn parse(
+    content: &str,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> Result<BTreeMap<String, String>> {
+    let mut out = BTreeMap::new();
+
+    for (i, line) in content.lines().enumerate() {
+        let line_no = i + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let line = line.strip_prefix("export ").map(str::trim_start).unwrap_or(line);
+        let (key, raw_value) = line.split_once('=')
+            .ok_or_else(|| anyhow!("line {}: expected `KEY=VALUE`", line_no))?;
+        let key = key.trim();
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            bail!("line {}: invalid variable name '{}'", line_no, key);
+        }
+
+        let lookup = |name: &str| out.get(name).cloned().or_else(|| lookup(name));
+        let raw_value = raw_value.trim();
+        let (value, rest) = if let Some(rest) = raw_value.strip_prefix('\'') {
+            let end = rest.find('\'')
+                .ok_or_else(|| anyhow!("line {}: unterminated single quote", line_no))?;
+            (rest[..end].to_owned(), &rest[end + 1..])
+        } else if let Some(rest) = raw_value.strip_prefix('"') {
+            expand(rest, true, lookup, line_no)?
+        } else {
+            // Unquoted values end at a comment.
+            let value = match raw_value.find(" #") {
+                Some(pos) => raw_value[..pos].trim_end(),
+                None => raw_value,
+            };
+            expand(value, false, lookup, line_no)?
+        };
+
+        // After a quoted value, only a comment may follow.
+        let rest = rest.trim_start();
+        if !rest.is_empty() && !rest.starts_with('#') {
+            bail!("line {}: unexpected '{}' after value", line_no, rest);
+        }
+
+        out.insert(key.to_owned(), value);
+    }
+
+    Ok(out)
+}
+
+/// Replaces all `${VAR}` in `s` and interprets escape sequences. `\$` always
+/// results in a literal `$`. If `quoted`, `s` starts right after a double
+/// quote: the value ends at the closing quote and the other escape sequences
+/// (e.g. `\n`) are interpreted as well. Returns the value and the rest of `s`
+/// after the value.
+fn expand(
+    s: &str,
+    quoted: bool,
+    lookup: impl Fn(&str) -> Option<String>,
+    line_no: usize,
+) -> Result<(String, &str)> {
+    let mut out = String::new();
+    let mut rest = s;
+    while let Some(c) = rest.chars().next() {
+        let after = &rest[c.len_utf8()..];
+        rest = match c {
+            '"' if quoted => return Ok((out, after)),
+            '\\' => {
+                let mut escaped = after.chars();
+                match escaped.next() {
+                    Some('$') => out.push('$'),
+                    Some('n') if quoted => out.push('\n'),
+                    Some('t') if quoted => out.push('\t'),
+                    Some(other) if quoted => out.push(other),
+                    _ => {
+                        out.push('\\');
+                        escaped = after.chars();
+                    }
+                }
+                escaped.as_str()
+            }
+            '$' if after.starts_with('{') => {
+                let end = after.find('}')
+                    .ok_or_else(|| anyhow!("line {}: unterminated `${{`", line_no))?;
+                out.push_str(&lookup(&after[1..end]).unwrap_or_default());
+                &after[end + 1..]
+            }
+            other => {
+                out.push(other);
+                after
+            }
+        };
+    }
+
+    if quoted {
+        bail!("line {}: unterminated double quote", line_no);
+    }
+    Ok((out, ""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_ok(content: &str) -> BTreeMap<String, String> {
+        let lookup = |name: &str| match name {
+            "FOO" => Some("foo".to_owned()),
+            _ => None,
+        };
+        parse(content, lookup).unwrap()
+    }
+
+    fn parse_err(content: &str) -> String {
+        parse(content, |_| None).unwrap_err().to_string()
+    }
+
+    fn single(content: &str) -> String {
+        parse_ok(content).remove("A").unwrap()
+    }
+
+    #[test]
+    fn unquoted() {
+        assert_eq!(single("A=bar"), "bar");
+        assert_eq!(single("export A = bar baz "), "bar baz");
+        assert_eq!(single("A=bar # comment"), "bar");
+        assert_eq!(single("A=x${FOO}y"), "xfooy");
+        assert_eq!(single("A=${UNKNOWN}"), "");
+        assert_eq!(single(r"A=a\nb"), r"a\nb");
+        assert_eq!(single(r"A=\${FOO}"), "${FOO}");
+    }
+
+    #[test]
+    fn single_quoted() {
+        assert_eq!(single(r"A='${FOO} \n # x'"), r"${FOO} \n # x");
+        assert_eq!(single("A='bar' # comment"), "bar");
+    }
+
+    #[test]
+    fn double_quoted() {
+        assert_eq!(single(r#"A="a\nb\tc""#), "a\nb\tc");
+        assert_eq!(single(r#"A="say \"hi\"""#), r#"say "hi""#);
+        assert_eq!(single(r#"A="${FOO} # no comment""#), "foo # no comment");
+        assert_eq!(single(r#"A="\${FOO}""#), "${FOO}");
+        assert_eq!(single(r#"A="\\${FOO}""#), r"\foo");
+        assert_eq!(single(r#"A="\\""#), r"\");
+    }
+
+    #[test]
+    fn earlier_variables() {
+        let vars = parse_ok("A=1\n# comment\n\nB=\"${A}2\"\nC=${B}3");
+        assert_eq!(vars["B"], "12");
+        assert_eq!(vars["C"], "123");
+    }
+
+    #[test]
+    fn trailing_text() {
+        assert!(parse_err(r#"A="x" junk"#).contains("unexpected 'junk' after value"));
+        assert!(parse_err("A='x'y").contains("unexpected 'y' after value"));
+        assert_eq!(single(r#"A="x"   # comment"#), "x");
+    }
+
+    #[test]
+    fn errors() {
+        assert!(parse_err("A").contains("line 1: expected `KEY=VALUE`"));
+        assert!(parse_err("\nA-B=1").contains("line 2: invalid variable name 'A-B'"));
+        assert!(parse_err(r#"A="x"#).contains("unterminated double quote"));
+        assert!(parse_err("A='x").contains("unterminated single quote"));
+        assert!(parse_err("A=${FOO").contains("unterminated `${`"));
+    }
+}
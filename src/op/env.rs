@@ -0,0 +1,130 @@
+//! Environment variables for the processes started by floof. Defines the
+//! `set-env` operation.
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+};
+use serde::{Deserializer, Deserialize, de::{self, Visitor}};
+use serde_yaml::{Mapping, Value};
+use crate::{
+    Context,
+    prelude::*,
+};
+use super::{Operation, Outcome, tagged};
+
+
+/// Environment variables stored in a context frame. All commands executed in
+/// that context (or any child context) get these variables. Variables of
+/// different frames are merged, see `Context::env`.
+#[derive(Debug, Clone, Default)]
+pub struct Env(pub BTreeMap<String, String>);
+
+/// Operation `set-env`: sets environment variables in the current context.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SetEnv(EnvMap);
+
+impl SetEnv {
+    pub const KEYWORD: &'static str = "set-env";
+}
+
+#[async_trait::async_trait]
+impl Operation for SetEnv {
+    fn keyword(&self) -> &'static str {
+        Self::KEYWORD
+    }
+
+    fn dyn_clone(&self) -> Box<dyn Operation> {
+        Box::new(self.clone())
+    }
+
+    fn describe(&self) -> Value {
+        tagged(Self::KEYWORD, &self.0)
+    }
+
+    async fn run(&self, ctx: &Context) -> Result<Outcome> {
+        // Multiple `set-env` operations in the same context add up.
+        let mut env = ctx.top_frame.get_var::<Env>().unwrap_or_default();
+        for (key, value) in &self.0.0 {
+            let value = ctx.interpolate(value)?;
+            verbose!(- [ctx]["set-env"] "set {[cyan]}={[cyan]}", key, value);
+            env.0.insert(key.clone(), value);
+        }
+        ctx.top_frame.insert_var(env);
+
+        Ok(Outcome::Success)
+    }
+}
+
+
+/// A map of environment variables (or other string variables) as specified in
+/// the configuration.
+///
+/// This mostly exists to deserialize values nicely: YAML happily interprets
+/// `PORT: 8000` as integer and `DEBUG: true` as boolean, but these variables
+/// are always strings. So all scalar values are accepted and converted to
+/// strings.
+#[derive(Debug, Clone, Default)]
+pub struct EnvMap(pub BTreeMap<String, String>);
+
+impl From<&EnvMap> for Value {
+    fn from(src: &EnvMap) -> Self {
+        src.0.iter()
+            .map(|(k, v)| (k.as_str().into(), v.as_str().into()))
+            .collect::<Mapping>()
+            .into()
+    }
+}
+
+impl<'de> Deserialize<'de> for EnvMap {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let map = BTreeMap::<String, ScalarString>::deserialize(deserializer)?;
+        Ok(Self(map.into_iter().map(|(k, v)| (k, v.0)).collect()))
+    }
+}
+
+/// A string that can be deserialized from any scalar value (string, number or
+/// boolean).
+#[derive(Debug, Clone)]
+pub struct ScalarString(pub String);
+
+impl<'de> Deserialize<'de> for ScalarString {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct ValueVisitor;
+        impl<'de> Visitor<'de> for ValueVisitor {
+            type Value = ScalarString;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a string, number or boolean")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                Ok(ScalarString(v.to_owned()))
+            }
+
+            fn visit_bool<E: de::Error>(self, v: bool) -> Result<Self::Value, E> {
+                Ok(ScalarString(v.to_string()))
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+                Ok(ScalarString(v.to_string()))
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+                Ok(ScalarString(v.to_string()))
+            }
+
+            fn visit_f64<E: de::Error>(self, v: f64) -> Result<Self::Value, E> {
+                Ok(ScalarString(v.to_string()))
+            }
+        }
+
+        deserializer.deserialize_any(ValueVisitor)
+    }
+}
@@ -2,11 +2,12 @@ use anyhow::{bail, Error};
 use penguin::ProxyTarget;
 use std::{convert::TryFrom, fmt, net::{SocketAddr, ToSocketAddrs}, time::Duration};
 use serde::Deserialize;
+use serde_yaml::Value;
 use crate::{
     Context,
     prelude::*,
 };
-use super::{Operation, Outcome};
+use super::{LintScope, Operation, Outcome, ParentKind, fields, optional, tagged};
 
 
 /// An HTTP server able to function as a reverse proxy or static file server.
@@ -35,6 +36,14 @@ impl Operation for Http {
         Box::new(self.clone())
     }
 
+    fn describe(&self) -> Value {
+        tagged(Self::KEYWORD, fields([
+            ("proxy", optional(self.proxy.clone())),
+            ("serve", optional(self.serve.clone())),
+            ("addr", optional(self.addr.map(|a| a.0.to_string()))),
+        ]))
+    }
+
     async fn run(&self, ctx: &Context) -> Result<Outcome> {
         let default_addr: SocketAddr = "127.0.0.1:8030".parse().unwrap();
 
@@ -43,11 +52,11 @@ impl Operation for Http {
 
         // Prepare configuration for dev server
         let proxy = self.proxy.as_ref()
-            .map(|s| s.parse::<ProxyTarget>())
+            .map(|s| ctx.interpolate(s)?.parse::<ProxyTarget>().map_err(Error::from))
             .transpose()?;
+        let serve = self.serve.as_ref().map(|s| ctx.interpolate(s)).transpose()?;
 
-        let builder = match (&proxy, &self.serve) {
-            // TODO: actually check that in validation
+        let builder = match (&proxy, &serve) {
             (None, None) | (Some(_), Some(_)) => panic!("bug: invalid config"),
             (Some(target), None) => builder.proxy(target.clone()),
             (None, Some(path)) => builder.add_mount("/", path).unwrap(),
@@ -62,6 +71,14 @@ impl Operation for Http {
 
         Ok(Outcome::Success)
     }
+
+    fn validate(&self, _parent: ParentKind<'_>, _config: &Config) -> Vec<Error> {
+        if self.proxy.is_some() == self.serve.is_some() {
+            return vec![anyhow!("exactly one of `proxy` and `serve` has to be specified")];
+        }
+
+        vec![]
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -88,6 +105,10 @@ impl Operation for Reload {
         Box::new(self.clone())
     }
 
+    fn describe(&self) -> Value {
+        tagged(Self::KEYWORD, Value::Null)
+    }
+
     async fn run(&self, ctx: &Context) -> Result<Outcome> {
         match ctx.get_closest_var::<Reloader>() {
             Some(reloader) => {
@@ -103,6 +124,14 @@ impl Operation for Reload {
             }
         }
     }
+
+    fn lint(&self, scope: &LintScope<'_>) -> Vec<String> {
+        if scope.has_http == Some(false) {
+            vec!["no enclosing `http` operation, so there is nothing to reload".into()]
+        } else {
+            vec![]
+        }
+    }
 }
 
 async fn reload_async(reloader: Reloader, ctx: Context) {
@@ -1,23 +1,34 @@
-use std::fmt;
+use std::{fmt, path::Path};
 use anyhow::Result;
-use crate::prelude::*;
+use serde_yaml::{Mapping, Value};
+use crate::{prelude::*, task::AtOperation};
 
+mod cached;
 mod command;
 mod concurrently;
 mod copy;
+mod dotenv;
+mod env;
 mod http;
 mod run_task;
+mod timeout;
+mod var;
 mod watch;
 mod workdir;
 
 pub use self::{
+    cached::Cached,
     command::Command,
     concurrently::Concurrently,
     copy::Copy,
+    dotenv::Dotenv,
+    env::{Env, EnvMap, ScalarString, SetEnv},
     http::{Http, Reload},
     run_task::RunTask,
-    watch::{OnChange, Watch},
-    workdir::{WorkDir, SetWorkDir},
+    timeout::Timeout,
+    var::{SetVar, Vars},
+    watch::{ForcePolling, OnChange, Watch},
+    workdir::{ConfigDir, WorkDir, SetWorkDir},
 };
 
 
@@ -37,16 +48,124 @@ pub trait Operation: fmt::Debug + 'static + Send + Sync {
     /// Runs the operation.
     async fn run(&self, ctx: &Context) -> Result<Outcome>;
 
+    /// Returns this operation in configuration syntax. Used to show operations
+    /// to the user, e.g. in error messages. The `Display` impl of `dyn
+    /// Operation` renders this as YAML.
+    fn describe(&self) -> Value;
+
     /// Validates the operation's configuration. The implementing type can
-    /// return an error here to indicate that the configuration has some logic
-    /// errors. This is called after parsing the configuration file.
-    fn validate(&self, _parent: ParentKind<'_>, _config: &Config) -> Result<()> {
-        Ok(())
+    /// return errors here to indicate that the configuration has some logic
+    /// errors. This is called after parsing the configuration file. Operations
+    /// with nested operations also return the errors of those (see
+    /// `validate_all`).
+    fn validate(&self, _parent: ParentKind<'_>, _config: &Config) -> Vec<Error> {
+        vec![]
+    }
+
+    /// Returns the operations nested in this operation, e.g. the `run` list
+    /// of `watch`. Used to inspect the configuration.
+    fn children(&self) -> &[Box<dyn Operation>] {
+        &[]
+    }
+
+    /// Mutable version of `children`.
+    fn children_mut(&mut self) -> &mut [Box<dyn Operation>] {
+        &mut []
+    }
+
+    /// Returns the name of the task this operation runs, if any.
+    fn referenced_task(&self) -> Option<&str> {
+        None
+    }
+
+    /// Mutable version of `referenced_task`.
+    fn referenced_task_mut(&mut self) -> Option<&mut String> {
+        None
+    }
+
+    /// Returns warnings about the configuration of this operation. Unlike
+    /// errors from `validate`, these do not prevent floof from running, but
+    /// likely indicate a mistake. Used by `floof check`.
+    fn lint(&self, _scope: &LintScope<'_>) -> Vec<String> {
+        vec![]
     }
 
     fn dyn_clone(&self) -> Box<dyn Operation>;
 }
 
+/// Collects the names of all tasks referenced by the given operations,
+/// including nested ones (see `Operation::referenced_task`).
+pub fn referenced_tasks<'a>(ops: &'a [Box<dyn Operation>], out: &mut Vec<&'a str>) {
+    for op in ops {
+        out.extend(op.referenced_task());
+        referenced_tasks(op.children(), out);
+    }
+}
+
+/// Helper for `Operation::validate`: validates the nested operations and
+/// returns all errors. Each error refers to the failing operation (see
+/// `AtOperation`), so that it can be located in the configuration file.
+pub(crate) fn validate_all(
+    ops: &[Box<dyn Operation>],
+    parent: ParentKind<'_>,
+    config: &Config,
+) -> Vec<Error> {
+    ops.iter()
+        .enumerate()
+        .flat_map(|(index, op)| {
+            op.validate(parent, config).into_iter().map(move |e| AtOperation::wrap(e, index, &**op))
+        })
+        .collect()
+}
+
+/// Prepends `namespace` to the names of all tasks referenced by the given
+/// operations, including nested ones. Used for tasks of included files.
+pub fn qualify_task_names(ops: &mut [Box<dyn Operation>], namespace: &str) {
+    for op in ops {
+        if let Some(task) = op.referenced_task_mut() {
+            task.insert_str(0, namespace);
+        }
+        qualify_task_names(op.children_mut(), namespace);
+    }
+}
+
+impl fmt::Display for dyn Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let yaml = serde_yaml::to_string(&self.describe()).map_err(|_| fmt::Error)?;
+        f.write_str(yaml.trim_start_matches("---\n").trim_end())
+    }
+}
+
+/// Helper for `Operation::describe`: returns a map with the keyword as single
+/// key, like operations are written in the configuration.
+fn tagged(keyword: &str, value: impl Into<Value>) -> Value {
+    let mut map = Mapping::new();
+    map.insert(keyword.into(), value.into());
+    map.into()
+}
+
+/// Helper for `Operation::describe`: returns a map with the given fields.
+/// Fields with `null` values are omitted, so that optional fields that are not
+/// set can be passed as `Value::Null`.
+pub(crate) fn fields<'a>(fields: impl IntoIterator<Item = (&'a str, Value)>) -> Value {
+    fields.into_iter()
+        .filter(|(_, v)| !v.is_null())
+        .map(|(k, v)| (Value::from(k), v))
+        .collect::<Mapping>()
+        .into()
+}
+
+/// Helper for `Operation::describe`: returns the descriptions of all
+/// operations as sequence.
+pub(crate) fn describe_all(ops: &[Box<dyn Operation>]) -> Value {
+    ops.iter().map(|op| op.describe()).collect()
+}
+
+/// Helper for `Operation::describe`: turns `None` into `null`.
+pub(crate) fn optional<T: Into<Value>>(v: Option<T>) -> Value {
+    v.map_or(Value::Null, Into::into)
+}
+
 impl Clone for Box<dyn Operation> {
     fn clone(&self) -> Self {
         self.dyn_clone()
@@ -67,7 +186,7 @@ impl Outcome {
         *self == Self::Success
     }
 
-    pub fn to_exit_code(&self) -> i32 {
+    pub fn to_exit_code(self) -> i32 {
         match self {
             Self::Success => 0,
             Self::Failure => 1,
@@ -76,6 +195,19 @@ impl Outcome {
     }
 }
 
+/// What is statically known about the context an operation runs in. Passed to
+/// `Operation::lint`.
+#[derive(Debug, Clone, Copy)]
+pub struct LintScope<'a> {
+    /// The workdir the operation runs in, if it is known without running
+    /// anything (e.g. `set-workdir` changes it).
+    pub workdir: Option<&'a Path>,
+
+    /// Whether an `http` operation runs in the same or an enclosing context.
+    /// `None` if unknown, e.g. for tasks started via `run-task`.
+    pub has_http: Option<bool>,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ParentKind<'a> {
     /// Operation of a task with the given name.
@@ -1,13 +1,45 @@
 use serde::Deserialize;
+use serde_yaml::Value;
 use crate::{
     Config,
+    TaskArgs,
     prelude::*,
 };
-use super::{Operation, Outcome, ParentKind};
+use super::{EnvMap, Operation, Outcome, ParentKind, fields, tagged};
 
 
+/// Operation `run-task`: runs another task, optionally with arguments for its
+/// parameters.
 #[derive(Debug, Clone, Deserialize)]
-pub struct RunTask(String);
+#[serde(from = "RawRunTask")]
+pub struct RunTask {
+    task: String,
+    args: TaskArgs,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawRunTask {
+    Simple(String),
+    Explicit(ExplicitRunTask),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ExplicitRunTask {
+    task: String,
+    #[serde(default)]
+    params: EnvMap,
+}
+
+impl From<RawRunTask> for RunTask {
+    fn from(src: RawRunTask) -> Self {
+        match src {
+            RawRunTask::Simple(task) => Self { task, args: TaskArgs::new() },
+            RawRunTask::Explicit(e) => Self { task: e.task, args: e.params.0 },
+        }
+    }
+}
 
 impl RunTask {
     pub const KEYWORD: &'static str = "run-task";
@@ -23,28 +55,42 @@ impl Operation for RunTask {
         Box::new(self.clone())
     }
 
+    fn describe(&self) -> Value {
+        let value = if self.args.is_empty() {
+            self.task.clone().into()
+        } else {
+            fields([
+                ("task", self.task.clone().into()),
+                ("params", (&EnvMap(self.args.clone())).into()),
+            ])
+        };
+
+        tagged(Self::KEYWORD, value)
+    }
+
     async fn run(&self, ctx: &Context) -> Result<Outcome> {
-        let task_name = self.0.clone();
-        let task = &ctx.config.tasks[&task_name];
-
-        let op_ctx = ctx.fork_task(&task_name);
-        for op in &task.operations {
-            let outcome = op.run(&op_ctx).await?;
-            if !outcome.is_success() {
-                return Ok(outcome);
-            }
-        }
+        let task = &ctx.config.tasks[&self.task];
+        let args = self.args.iter()
+            .map(|(name, value)| Ok((name.clone(), ctx.interpolate(value)?)))
+            .collect::<Result<_>>()?;
 
-        Ok(Outcome::Success)
+        task.run(ctx, &args).await
     }
 
-    fn validate(&self, _parent: ParentKind<'_>, config: &Config) -> Result<()> {
-        // TODO: maybe disallow recursion?
+    fn referenced_task(&self) -> Option<&str> {
+        Some(&self.task)
+    }
 
-        if !config.tasks.contains_key(&self.0) {
-            bail!("task '{}' does not exist", self.0);
-        }
+    fn referenced_task_mut(&mut self) -> Option<&mut String> {
+        Some(&mut self.task)
+    }
 
-        Ok(())
+    fn validate(&self, _parent: ParentKind<'_>, config: &Config) -> Vec<Error> {
+        // Recursion is checked by `Config::validate`.
+        let result = match config.tasks.get(&self.task) {
+            None => Err(anyhow!("task '{}' does not exist", self.task)),
+            Some(task) => task.check_arg_names(&self.args),
+        };
+        result.err().into_iter().collect()
     }
 }
@@ -0,0 +1,82 @@
+use serde::Deserialize;
+use serde_yaml::Value;
+use crate::{
+    Context,
+    duration::HumanDuration,
+    prelude::*,
+};
+use super::{
+    Operation, Operations, Outcome, ParentKind,
+    describe_all, fields, tagged, validate_all,
+};
+
+
+/// Operation `timeout`: runs operations, but cancels them if they do not
+/// finish within the given duration. In that case, the operation fails.
+///
+/// Like `on-change`, this is a transparent wrapper: the operations are
+/// executed in the same context as the `timeout` operation itself.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Timeout {
+    duration: HumanDuration,
+    run: Operations,
+}
+
+impl Timeout {
+    pub const KEYWORD: &'static str = "timeout";
+}
+
+#[async_trait::async_trait]
+impl Operation for Timeout {
+    fn keyword(&self) -> &'static str {
+        Self::KEYWORD
+    }
+
+    fn dyn_clone(&self) -> Box<dyn Operation> {
+        Box::new(self.clone())
+    }
+
+    fn describe(&self) -> Value {
+        tagged(Self::KEYWORD, fields([
+            ("duration", format!("{:?}", self.duration).into()),
+            ("run", describe_all(&self.run)),
+        ]))
+    }
+
+    fn children(&self) -> &[Box<dyn Operation>] {
+        &self.run
+    }
+
+    fn children_mut(&mut self) -> &mut [Box<dyn Operation>] {
+        &mut self.run
+    }
+
+    async fn run(&self, ctx: &Context) -> Result<Outcome> {
+        let run_all = async {
+            for op in &self.run {
+                let outcome = op.run(ctx).await?;
+                if !outcome.is_success() {
+                    return Ok(outcome);
+                }
+            }
+
+            Ok(Outcome::Success)
+        };
+
+        match tokio::time::timeout(self.duration.0, run_all).await {
+            Ok(outcome) => outcome,
+            Err(_) => {
+                msg!(warn [ctx]["timeout"]
+                    "operations did not finish within {:?} → cancelled them",
+                    self.duration,
+                );
+                Ok(Outcome::Failure)
+            }
+        }
+    }
+
+    fn validate(&self, parent: ParentKind<'_>, config: &Config) -> Vec<Error> {
+        validate_all(&self.run, parent, config)
+    }
+}
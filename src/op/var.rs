@@ -0,0 +1,57 @@
+//! User defined variables. Defines the `set-var` operation.
+
+use std::collections::BTreeMap;
+use serde::Deserialize;
+use serde_yaml::Value;
+use crate::{
+    Context,
+    prelude::*,
+};
+use super::{EnvMap, Operation, Outcome, tagged};
+
+
+/// Named string variables stored in a context frame, e.g. by `set-var` or the
+/// `capture` property of `command`. Operations can access the closest
+/// variable of a given name via `Context::user_var`.
+#[derive(Debug, Clone, Default)]
+pub struct Vars {
+    pub values: BTreeMap<String, String>,
+
+    /// The items of list-valued variables (e.g. the changed files of `watch`),
+    /// see `Context::user_var_list`. Their value in `values` is the items
+    /// joined by spaces.
+    pub lists: BTreeMap<String, Vec<String>>,
+}
+
+/// Operation `set-var`: sets user defined variables in the current context.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SetVar(EnvMap);
+
+impl SetVar {
+    pub const KEYWORD: &'static str = "set-var";
+}
+
+#[async_trait::async_trait]
+impl Operation for SetVar {
+    fn keyword(&self) -> &'static str {
+        Self::KEYWORD
+    }
+
+    fn dyn_clone(&self) -> Box<dyn Operation> {
+        Box::new(self.clone())
+    }
+
+    fn describe(&self) -> Value {
+        tagged(Self::KEYWORD, &self.0)
+    }
+
+    async fn run(&self, ctx: &Context) -> Result<Outcome> {
+        for (name, value) in &self.0.0 {
+            let value = ctx.interpolate(value)?;
+            verbose!(- [ctx]["set-var"] "set {[cyan]}={[cyan]}", name, value);
+            ctx.set_user_var(name, value);
+        }
+
+        Ok(Outcome::Success)
+    }
+}
@@ -2,25 +2,59 @@
 //! Defines the `watch` and `on-change` operations.
 
 use std::{
+    collections::BTreeMap,
+    fmt,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
     time::Duration,
-    path::Path,
 };
-use notify::{Watcher, RecursiveMode, RecommendedWatcher};
-use serde::Deserialize;
+use notify::{
+    PollWatcher, Watcher, RecursiveMode, RecommendedWatcher,
+    event::{AccessKind, AccessMode, EventKind, ModifyKind, RenameMode},
+};
+use serde::{Deserialize, Deserializer, de::{self, MapAccess, SeqAccess, Visitor}};
+use serde_yaml::Value;
 use tokio::sync::watch;
 
-use crate::prelude::*;
-use super::{Operation, Operations, Outcome, ParentKind};
+use crate::{
+    prelude::*,
+    cfg,
+    duration::HumanDuration,
+    glob::{self, PathPattern},
+    ignore::IgnoreRules,
+    schedule::DependencyRuns,
+};
+use super::{
+    LintScope, Operation, Operations, Outcome, ParentKind,
+    describe_all, fields, optional, tagged, validate_all,
+};
 
 
 /// The duration for which we debounce watch events.
 const DEFAULT_DEBOUNCE_DURATION: Duration = Duration::from_millis(250);
 
-/// Operation `on-change`. Wraps another operation and only executes it if the
+/// The polling interval if polling is forced via `ForcePolling`, but `poll` is
+/// not specified.
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Stored in the root frame: if `true`, all `watch` operations use polling
+/// (set via `--poll`).
+#[derive(Debug, Clone, Copy)]
+pub struct ForcePolling(pub bool);
+
+/// Operation `on-change`. Wraps operations and only executes them if the
 /// operation was triggered by a file change in the parent `watch` operation. If
 /// not used as a direct child of `watch`, validation will error.
-#[derive(Debug, Clone, Deserialize)]
-pub struct OnChange(Box<dyn Operation>);
+///
+/// Can be specified as single operation or as map with `run` and optionally
+/// `paths`, which are distinguished by the first key.
+#[derive(Debug, Clone)]
+pub struct OnChange {
+    /// If set, the operations are only executed if a file matching one of
+    /// these patterns changed.
+    paths: Option<Vec<String>>,
+    run: Operations,
+}
 
 impl OnChange {
     pub const KEYWORD: &'static str = "on-change";
@@ -36,36 +70,239 @@ impl Operation for OnChange {
         Box::new(self.clone())
     }
 
+    fn describe(&self) -> Value {
+        match (&self.paths, self.run.as_slice()) {
+            (None, [op]) => tagged(Self::KEYWORD, op.describe()),
+            (paths, _) => tagged(Self::KEYWORD, fields([
+                ("paths", optional(paths.clone())),
+                ("run", describe_all(&self.run)),
+            ])),
+        }
+    }
+
+    fn children(&self) -> &[Box<dyn Operation>] {
+        &self.run
+    }
+
+    fn children_mut(&mut self) -> &mut [Box<dyn Operation>] {
+        &mut self.run
+    }
+
     async fn run(&self, ctx: &Context) -> Result<Outcome> {
-        // TODO: validate this when parsing AND ... only top frame? Probably
-        // just "closest var" I think.
-        if ctx.top_frame.get_var::<TriggeredByChange>().expect("bug: not in watch context").0 {
-            self.0.run(ctx).await
-        } else {
-            Ok(Outcome::Success)
+        // Validation makes sure we are inside `watch`.
+        let triggered = ctx.get_closest_var::<TriggeredByChange>()
+            .expect("bug: not in watch context")
+            .0;
+        if !triggered {
+            return Ok(Outcome::Success);
+        }
+
+        if let Some(paths) = &self.paths {
+            let patterns = paths.iter()
+                .map(|path| PathPattern::in_workdir(path, ctx))
+                .collect::<Result<Vec<_>>>()?;
+            let changes = ctx.get_closest_var::<ChangedFiles>().expect("bug: not in watch context");
+            let relevant = changes.0.keys()
+                .any(|path| patterns.iter().any(|pattern| pattern.matches(path)));
+            if !relevant {
+                verbose!(- [ctx]["on-change"] "no changes in {:?} → skipping", paths);
+                return Ok(Outcome::Success);
+            }
+        }
+
+        for op in &self.run {
+            let outcome = op.run(ctx).await?;
+            if !outcome.is_success() {
+                return Ok(outcome);
+            }
         }
+
+        Ok(Outcome::Success)
     }
 
-    fn validate(&self, parent: ParentKind<'_>, _config: &Config) -> Result<()> {
+    fn validate(&self, parent: ParentKind<'_>, config: &Config) -> Vec<Error> {
+        let mut errors = Vec::new();
         if parent != ParentKind::Operation("watch") {
-            bail!("`on-change` operation can only be used in the `run` \
-                array of a `watch` operation");
+            errors.push(anyhow!("`on-change` operation can only be used in the `run` \
+                array of a `watch` operation"));
         }
 
-        Ok(())
+        if let Some(paths) = &self.paths {
+            if paths.is_empty() {
+                errors.push(anyhow!("`paths` of `on-change` must not be empty"));
+            }
+            errors.extend(paths.iter().filter_map(|path| PathPattern::check_syntax(path).err()));
+        }
+
+        errors.extend(validate_all(&self.run, ParentKind::Operation(Self::KEYWORD), config));
+        errors
+    }
+}
+
+impl<'de> Deserialize<'de> for OnChange {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct OnChangeVisitor;
+        impl<'de> Visitor<'de> for OnChangeVisitor {
+            type Value = OnChange;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("an operation or a map with a `run` field")
+            }
+
+            fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                let op = Deserialize::deserialize(de::value::StrDeserializer::new(v))?;
+                Ok(OnChange { paths: None, run: vec![op] })
+            }
+
+            fn visit_seq<A>(self, seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: SeqAccess<'de>,
+            {
+                let op = Deserialize::deserialize(de::value::SeqAccessDeserializer::new(seq))?;
+                Ok(OnChange { paths: None, run: vec![op] })
+            }
+
+            fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+            where
+                A: MapAccess<'de>,
+            {
+                const FIELDS: &[&str] = &["paths", "run"];
+
+                let first = map.next_key::<String>()?
+                    .ok_or_else(|| de::Error::invalid_length(0, &"1"))?;
+                if !FIELDS.contains(&first.as_str()) {
+                    let op = cfg::deserialize_tagged_op(&first, &mut map)?;
+                    return Ok(OnChange { paths: None, run: vec![op] });
+                }
+
+                let mut paths = None;
+                let mut run = None;
+                let mut key = Some(first);
+                while let Some(k) = key {
+                    match k.as_str() {
+                        "paths" if paths.is_some() => return Err(de::Error::duplicate_field("paths")),
+                        "paths" => paths = Some(map.next_value()?),
+                        "run" if run.is_some() => return Err(de::Error::duplicate_field("run")),
+                        "run" => run = Some(map.next_value()?),
+                        other => return Err(de::Error::unknown_field(other, FIELDS)),
+                    }
+                    key = map.next_key()?;
+                }
+
+                let run = run.ok_or_else(|| de::Error::missing_field("run"))?;
+                Ok(OnChange { paths, run })
+            }
+        }
+
+        // See `impl_deserialize_for_op` for why `deserialize_any` is fine.
+        deserializer.deserialize_any(OnChangeVisitor)
     }
 }
 
 #[derive(Clone, Copy)]
 struct TriggeredByChange(bool);
 
+/// How a file changed, see `ChangedFiles`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ChangeKind {
+    Created,
+    Modified,
+    Removed,
+}
+
+/// The files that changed since the operations of `watch` last ran to
+/// completion. Stored in the frame of `watch` next to `TriggeredByChange`.
+#[derive(Debug, Clone, Default)]
+struct ChangedFiles(BTreeMap<PathBuf, ChangeKind>);
+
+impl ChangedFiles {
+    /// Records all changes of the event, except for paths for which
+    /// `is_relevant` returns `false`.
+    fn record_event(&mut self, event: &notify::Event, is_relevant: impl Fn(&Path) -> bool) {
+        for (i, path) in event.paths.iter().enumerate() {
+            let kind = match event.kind {
+                EventKind::Create(_) => ChangeKind::Created,
+                EventKind::Remove(_) => ChangeKind::Removed,
+                EventKind::Modify(ModifyKind::Name(RenameMode::From)) => ChangeKind::Removed,
+                EventKind::Modify(ModifyKind::Name(RenameMode::To)) => ChangeKind::Created,
+                // The paths are `[from, to]`.
+                EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if i == 0 => ChangeKind::Removed,
+                EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => ChangeKind::Created,
+                EventKind::Modify(ModifyKind::Name(_)) if path.exists() => ChangeKind::Created,
+                EventKind::Modify(ModifyKind::Name(_)) => ChangeKind::Removed,
+                EventKind::Access(AccessKind::Close(AccessMode::Write)) => ChangeKind::Modified,
+                EventKind::Access(_) => continue,
+                _ => ChangeKind::Modified,
+            };
+
+            if is_relevant(path) {
+                self.record(path.clone(), kind);
+            }
+        }
+    }
+
+    /// Records a change, combined with earlier changes of the same path.
+    fn record(&mut self, path: PathBuf, kind: ChangeKind) {
+        use ChangeKind::*;
+        let combined = match (self.0.get(&path), kind) {
+            (Some(Created), Modified) => Some(Created),
+            (Some(Created), Removed) => None,
+            (Some(Removed), Created) => Some(Modified),
+            _ => Some(kind),
+        };
+
+        match combined {
+            Some(kind) => self.0.insert(path, kind),
+            None => self.0.remove(&path),
+        };
+    }
+
+    fn merge(&mut self, other: ChangedFiles) {
+        for (path, kind) in other.0 {
+            self.record(path, kind);
+        }
+    }
+
+    /// Returns the paths with one of the given kinds, separated by spaces.
+    /// Paths inside `base` are relative to it.
+    fn paths(&self, kinds: &[ChangeKind], base: &Path) -> Vec<String> {
+        self.0.iter()
+            .filter(|(_, kind)| kinds.contains(kind))
+            .map(|(path, _)| path.strip_prefix(base).unwrap_or(path).display().to_string())
+            .collect()
+    }
+
+    /// Makes the changes available as list-valued user defined variables in
+    /// the top frame of `ctx`.
+    fn set_vars(&self, ctx: &Context, base: &Path) {
+        use ChangeKind::*;
+        ctx.set_user_var_list("changed_files", self.paths(&[Created, Modified], base));
+        ctx.set_user_var_list("created_files", self.paths(&[Created], base));
+        ctx.set_user_var_list("modified_files", self.paths(&[Modified], base));
+        ctx.set_user_var_list("removed_files", self.paths(&[Removed], base));
+    }
+}
+
 #[derive(Debug, Clone, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Watch {
     paths: Vec<String>,
     run: Operations,
     debounce: Option<u64>,
-    // TODO: flag to enable polling?
+    #[serde(default)]
+    ignore: Vec<String>,
+    #[serde(default)]
+    gitignore: bool,
+
+    /// If set, the file system is polled with this interval instead of relying
+    /// on change notifications of the OS.
+    poll: Option<HumanDuration>,
 }
 
 impl Watch {
@@ -82,6 +319,25 @@ impl Operation for Watch {
         Box::new(self.clone())
     }
 
+    fn describe(&self) -> Value {
+        tagged(Self::KEYWORD, fields([
+            ("paths", self.paths.clone().into()),
+            ("debounce", optional(self.debounce)),
+            ("ignore", if self.ignore.is_empty() { Value::Null } else { self.ignore.clone().into() }),
+            ("gitignore", if self.gitignore { true.into() } else { Value::Null }),
+            ("poll", optional(self.poll.map(|d| format!("{:?}", d)))),
+            ("run", describe_all(&self.run)),
+        ]))
+    }
+
+    fn children(&self) -> &[Box<dyn Operation>] {
+        &self.run
+    }
+
+    fn children_mut(&mut self) -> &mut [Box<dyn Operation>] {
+        &mut self.run
+    }
+
     async fn run(&self, ctx: &Context) -> Result<Outcome> {
         // ===== Prepare watcher =================================================================
         //
@@ -89,24 +345,77 @@ impl Operation for Watch {
         // watcher is not async, so the easiest way to get events into our async
         // world is to send them through a channel. Once the `watcher` is
         // dropped, it no longer watches anything.
+        let patterns = self.paths.iter()
+            .map(|path| PathPattern::in_workdir(path, ctx))
+            .collect::<Result<Vec<_>>>()?;
+        if let Some(missing) = patterns.iter().find(|p| !p.base.exists()) {
+            bail!("path '{}' does not exist", missing.base.display());
+        }
+
+        // Rules of the `ignore` property come last to take precedence.
+        let mut ignore = IgnoreRules::default();
+        if self.gitignore {
+            let roots = patterns.iter().map(|p| p.base.clone()).collect::<Vec<_>>();
+            ignore = tokio::task::spawn_blocking(move || -> Result<IgnoreRules> {
+                let mut rules = IgnoreRules::default();
+                for root in roots {
+                    rules.add_ignore_files(&root)?;
+                }
+                Ok(rules)
+            }).await??;
+        }
+        let workdir = ctx.workdir();
+        for pattern in &self.ignore {
+            ignore.add(&workdir, &ctx.interpolate(pattern)?)?;
+        }
+
+        // For glob patterns, we watch their base directory and ignore events
+        // for paths not matching any pattern. Errors are passed through.
+        let globs = patterns.iter().any(PathPattern::is_glob).then(|| patterns.clone());
+        let is_relevant = move |path: &Path| {
+            let matches_glob = match &globs {
+                Some(globs) => globs.iter().any(|pattern| pattern.matches(path)),
+                None => true,
+            };
+            matches_glob && !ignore.is_ignored(path)
+        };
+
+        // The changed files are collected in the watcher thread and taken out
+        // whenever the operations are started.
+        let collected_changes = Arc::new(Mutex::new(ChangedFiles::default()));
         let (watch_event_tx, mut watch_events) = watch::channel(());
-        let mut watcher = RecommendedWatcher::new(move |_ev| {
-            watch_event_tx.send(()).expect("bug: executor thread unexpectedly ended");
-        })?;
+        let handler = {
+            let collected_changes = collected_changes.clone();
+            move |ev: notify::Result<notify::Event>| {
+                if let Ok(ev) = &ev {
+                    if !ev.paths.is_empty() && !ev.paths.iter().any(|path| is_relevant(path)) {
+                        return;
+                    }
 
-        // Add paths to watch.
-        let base = ctx.workdir();
-        for path in &self.paths {
-            let mut path = Path::new(path).to_path_buf();
-            if path.is_relative() {
-                path = base.join(path);
+                    collected_changes.lock().unwrap().record_event(ev, &is_relevant);
+                }
+
+                watch_event_tx.send(()).expect("bug: executor thread unexpectedly ended");
             }
+        };
 
-            if !path.exists() {
-                bail!("path '{}' does not exist", path.display());
+        let force_polling = matches!(ctx.get_closest_var::<ForcePolling>(), Some(ForcePolling(true)));
+        let poll_interval = match self.poll {
+            Some(interval) => Some(interval.0),
+            None if force_polling => Some(DEFAULT_POLL_INTERVAL),
+            None => None,
+        };
+        let mut watcher: Box<dyn Watcher + Send> = match poll_interval {
+            Some(interval) => {
+                verbose!(- [ctx]["watch"] "polling for changes every {:?}", HumanDuration(interval));
+                Box::new(PollWatcher::with_delay(Arc::new(Mutex::new(handler)), interval)?)
             }
+            None => Box::new(RecommendedWatcher::new(handler)?),
+        };
 
-            watcher.watch(&path, RecursiveMode::Recursive)?;
+        // Add paths to watch.
+        for pattern in &patterns {
+            watcher.watch(&pattern.base, RecursiveMode::Recursive)?;
         }
 
 
@@ -124,6 +433,7 @@ impl Operation for Watch {
 
         // Run the state machine forever.
         let mut state = State::Run { triggered_by_change: false };
+        let mut changes = ChangedFiles::default();
         'main: loop {
             match state {
                 State::WaitingForChange => {
@@ -156,6 +466,18 @@ impl Operation for Watch {
                     }
 
                     op_ctx.top_frame.insert_var(TriggeredByChange(triggered_by_change));
+
+                    // Changes that triggered a run that got cancelled are kept,
+                    // as the operations did not finish for them.
+                    let new_changes = std::mem::take(&mut *collected_changes.lock().unwrap());
+                    if triggered_by_change {
+                        changes.merge(new_changes);
+                    }
+                    changes.set_vars(&op_ctx, &workdir);
+                    op_ctx.top_frame.insert_var(changes.clone());
+
+                    // Files changed, so dependencies have to run again.
+                    op_ctx.top_frame.insert_var(DependencyRuns::default());
                     for op in &self.run {
                         let running = op.run(&op_ctx);
 
@@ -163,9 +485,9 @@ impl Operation for Watch {
                             outcome = running => {
                                 if !outcome?.is_success() {
                                     verbose!(
-                                        - [ctx] - "'{}' operation failed → stopping (no further \
-                                            operations of this task will run)",
-                                        op.keyword(),
+                                        - [ctx] - "operation failed → stopping (no further \
+                                            operations of this task will run):\n{}",
+                                        crate::ui::indented(op),
                                     );
 
                                     break;
@@ -186,11 +508,36 @@ impl Operation for Watch {
                         }
                     }
 
+                    changes = ChangedFiles::default();
                     state = State::WaitingForChange;
                 }
             }
         }
     }
+
+    fn lint(&self, scope: &LintScope<'_>) -> Vec<String> {
+        let workdir = match scope.workdir {
+            Some(workdir) => workdir,
+            None => return vec![],
+        };
+
+        // Paths with variables can only be checked when running. For glob
+        // patterns, only the directory the pattern starts in is checked.
+        self.paths.iter()
+            .filter(|path| !path.contains('$'))
+            .filter(|path| !workdir.join(glob::base_of(path)).exists())
+            .map(|path| format!("path '{}' does not exist", glob::base_of(path).display()))
+            .collect()
+    }
+
+    fn validate(&self, _parent: ParentKind<'_>, config: &Config) -> Vec<Error> {
+        let mut errors = self.paths.iter()
+            .filter_map(|path| PathPattern::check_syntax(path).err())
+            .collect::<Vec<_>>();
+        errors.extend(self.ignore.iter().filter_map(|line| IgnoreRules::check_syntax(line).err()));
+        errors.extend(validate_all(&self.run, ParentKind::Operation(Self::KEYWORD), config));
+        errors
+    }
 }
 
 
@@ -1,15 +1,21 @@
 use std::path::PathBuf;
 use serde::Deserialize;
+use serde_yaml::Value;
 use crate::{
     Context,
     prelude::*,
 };
-use super::{Operation, Outcome};
+use super::{Operation, Outcome, tagged};
 
 
 #[derive(Debug, Clone)]
 pub struct WorkDir(pub PathBuf);
 
+/// The directory of the configuration file defining the current task.
+/// Relative paths not starting with `./` are resolved against it.
+#[derive(Debug, Clone)]
+pub struct ConfigDir(pub PathBuf);
+
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct SetWorkDir(String);
@@ -28,8 +34,12 @@ impl Operation for SetWorkDir {
         Box::new(self.clone())
     }
 
+    fn describe(&self) -> Value {
+        tagged(Self::KEYWORD, self.0.clone())
+    }
+
     async fn run(&self, ctx: &Context) -> Result<Outcome> {
-        let new_workdir = ctx.join_workdir(&self.0);
+        let new_workdir = ctx.join_workdir(ctx.interpolate(&self.0)?);
         if !new_workdir.is_dir() {
             bail!(
                 "'{}' is not a valid path to a directory (or it is inaccessible)",
@@ -0,0 +1,322 @@
+//! Managing child processes started by floof, in particular stopping them when
+//! the operation that started them is cancelled.
+
+use std::{
+    convert::TryFrom,
+    fmt,
+    io,
+    process::ExitStatus,
+    sync::Arc,
+    time::Duration,
+};
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use tokio::{
+    process::{Child, ChildStderr, ChildStdout},
+    runtime::Handle,
+    sync::watch,
+};
+
+
+/// The default duration a process has to stop after receiving the stop signal
+/// before it is killed.
+pub const DEFAULT_STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// All processes that are currently being stopped, see `wait_for_stopping`.
+static ALL_STOPPING: Lazy<Stopping> = Lazy::new(Stopping::default);
+
+
+/// Counts processes that are currently being stopped, i.e. that received
+/// their stop signal but did not exit yet. Each process is counted by the
+/// `Stopping` given to `Process::spawn` and by a global one.
+#[derive(Clone)]
+pub struct Stopping(Arc<watch::Sender<usize>>);
+
+impl Default for Stopping {
+    fn default() -> Self {
+        Self(Arc::new(watch::channel(0).0))
+    }
+}
+
+impl fmt::Debug for Stopping {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Stopping").field(&*self.0.borrow()).finish()
+    }
+}
+
+impl Stopping {
+    /// Returns `true` if some processes are currently being stopped.
+    pub fn any(&self) -> bool {
+        *self.0.borrow() > 0
+    }
+
+    /// Waits until all processes that were asked to stop have exited (or were
+    /// killed). Returns immediately if there are no such processes.
+    pub async fn wait(&self) {
+        let mut rx = self.0.subscribe();
+        while *rx.borrow_and_update() > 0 {
+            // We hold the sender, so it's never dropped while waiting.
+            let _ = rx.changed().await;
+        }
+    }
+}
+
+/// Counts a process as stopping in all given counters while it exists.
+struct StoppingGuard(Vec<Stopping>);
+
+impl StoppingGuard {
+    fn new(counters: Vec<Stopping>) -> Self {
+        for counter in &counters {
+            counter.0.send_modify(|n| *n += 1);
+        }
+        Self(counters)
+    }
+}
+
+impl Drop for StoppingGuard {
+    fn drop(&mut self) {
+        for counter in &self.0 {
+            counter.0.send_modify(|n| *n -= 1);
+        }
+    }
+}
+
+/// Kills the process group with the given ID when dropped. That way, the
+/// group is killed even if stopping it gracefully is interrupted, e.g. when
+/// the runtime shuts down. The group leader itself is killed by `kill_on_drop`
+/// of its `Child`, which (unlike signalling its PID) is safe after it was
+/// reaped.
+struct KillGroupOnDrop(u32);
+
+impl Drop for KillGroupOnDrop {
+    fn drop(&mut self) {
+        send_signal(self.0, true, Signal::Kill);
+    }
+}
+
+
+/// Signals that can be sent to a process to ask it to stop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Signal {
+    Term,
+    Int,
+    Hup,
+    Quit,
+    Kill,
+}
+
+impl TryFrom<String> for Signal {
+    type Error = String;
+
+    fn try_from(src: String) -> Result<Self, Self::Error> {
+        let name = src.trim_start_matches("SIG");
+        match name {
+            "TERM" => Ok(Self::Term),
+            "INT" => Ok(Self::Int),
+            "HUP" => Ok(Self::Hup),
+            "QUIT" => Ok(Self::Quit),
+            "KILL" => Ok(Self::Kill),
+            _ => Err(format!(
+                "'{}' is not a valid stop signal (valid: SIGTERM, SIGINT, SIGHUP, SIGQUIT, SIGKILL)",
+                src,
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Signal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Self::Term => "SIGTERM",
+            Self::Int => "SIGINT",
+            Self::Hup => "SIGHUP",
+            Self::Quit => "SIGQUIT",
+            Self::Kill => "SIGKILL",
+        };
+        f.write_str(name)
+    }
+}
+
+#[cfg(unix)]
+impl Signal {
+    fn raw(self) -> libc::c_int {
+        match self {
+            Self::Term => libc::SIGTERM,
+            Self::Int => libc::SIGINT,
+            Self::Hup => libc::SIGHUP,
+            Self::Quit => libc::SIGQUIT,
+            Self::Kill => libc::SIGKILL,
+        }
+    }
+}
+
+/// Describes how a process is stopped when it is cancelled.
+#[derive(Debug, Clone, Copy)]
+pub struct StopBehavior {
+    /// The signal that is sent first. `None` means that the process is killed
+    /// immediately.
+    pub signal: Option<Signal>,
+
+    /// How long to wait for the process to exit after sending `signal` before
+    /// killing it.
+    pub timeout: Duration,
+
+    /// Whether the process is started in its own process group, which is
+    /// stopped as a whole. Otherwise, only the process itself is stopped.
+    pub group: bool,
+}
+
+/// A running child process. If this is dropped before the process exited, the
+/// process is stopped according to its `StopBehavior`.
+///
+/// On unix, processes are usually started as leader of a new process group
+/// (see `StopBehavior::group`). Many programs start child processes themselves
+/// (e.g. `npm run dev` or `cargo run`), so the whole group is stopped to not
+/// leave any of those running. Processes in a background group are stopped
+/// (SIGTTIN) when reading from the terminal, so their stdin is closed if it
+/// would be the terminal.
+pub struct Process {
+    child: Option<Child>,
+    stop: StopBehavior,
+
+    /// Counts this process while it is being stopped (in addition to the
+    /// global counter).
+    stopping: Stopping,
+}
+
+impl Process {
+    /// Spawns the given command.
+    pub fn spawn(
+        #[allow(unused_mut)]
+        mut command: std::process::Command,
+        stop: StopBehavior,
+        stopping: Stopping,
+    ) -> io::Result<Self> {
+        #[cfg(unix)]
+        if stop.group {
+            std::os::unix::process::CommandExt::process_group(&mut command, 0);
+            if unsafe { libc::isatty(libc::STDIN_FILENO) } == 1 {
+                command.stdin(std::process::Stdio::null());
+            }
+        }
+
+        let mut command = tokio::process::Command::from(command);
+        command.kill_on_drop(true);
+        let child = command.spawn()?;
+
+        Ok(Self { child: Some(child), stop, stopping })
+    }
+
+    /// Takes the handle to the process' stdout, if it was piped.
+    pub fn take_stdout(&mut self) -> Option<ChildStdout> {
+        self.child.as_mut()?.stdout.take()
+    }
+
+    /// Takes the handle to the process' stderr, if it was piped.
+    pub fn take_stderr(&mut self) -> Option<ChildStderr> {
+        self.child.as_mut()?.stderr.take()
+    }
+
+    /// Waits for the process to exit.
+    pub async fn wait(&mut self) -> io::Result<ExitStatus> {
+        let status = self.child.as_mut().expect("bug: process already exited").wait().await?;
+
+        // The process exited on its own, so there is nothing to stop anymore.
+        self.child = None;
+
+        Ok(status)
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        let mut child = match self.child.take() {
+            Some(child) => child,
+            None => return,
+        };
+
+        let pid = match child.id() {
+            Some(pid) => pid,
+            // The process already exited, but nobody waited for it.
+            None => return,
+        };
+
+        // The child itself is killed by `kill_on_drop` when dropped.
+        let kill_group = if self.stop.group { Some(KillGroupOnDrop(pid)) } else { None };
+        let signal = match self.stop.signal {
+            Some(signal) if signal != Signal::Kill && cfg!(unix) => signal,
+            _ => return,
+        };
+
+        // Stopping gracefully requires a runtime to wait in the background.
+        // Without one (e.g. while the runtime shuts down), the process is
+        // killed immediately.
+        let handle = match Handle::try_current() {
+            Ok(handle) => handle,
+            Err(_) => return,
+        };
+
+        // Ask the process to stop and give it some time to do so in the
+        // background. If it does not exit in time, it's killed. Other
+        // processes in the group might still be alive afterwards, so the group
+        // (if any) is killed in any case. The process is not signalled
+        // directly anymore, as its PID might have been reused after it was
+        // reaped.
+        send_signal(pid, self.stop.group, signal);
+        let timeout = self.stop.timeout;
+        let stopping = StoppingGuard::new(vec![self.stopping.clone(), ALL_STOPPING.clone()]);
+        handle.spawn(async move {
+            let _stopping = stopping;
+            let _kill_group = kill_group;
+            if tokio::time::timeout(timeout, child.wait()).await.is_err() {
+                let _ = child.start_kill();
+            }
+        });
+    }
+}
+
+/// Waits until all processes that were asked to stop have exited (or were
+/// killed). Returns immediately if there are no such processes.
+pub async fn wait_for_stopping() {
+    ALL_STOPPING.wait().await;
+}
+
+/// Resolves when floof is asked to shut down, i.e. when receiving SIGINT
+/// (Ctrl+C), SIGTERM or SIGHUP. As most child processes run in their own
+/// process groups, they do not receive the Ctrl+C from the terminal, so floof
+/// has to stop them.
+pub async fn shutdown_signal() -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut term = signal(SignalKind::terminate())?;
+        let mut hup = signal(SignalKind::hangup())?;
+        tokio::select! {
+            res = tokio::signal::ctrl_c() => res,
+            _ = term.recv() => Ok(()),
+            _ = hup.recv() => Ok(()),
+        }
+    }
+
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await
+}
+
+/// Sends the signal to the process with the given ID or, if `group` is set, to
+/// the process group led by it. Errors are ignored, as the process might not
+/// exist anymore.
+#[cfg(unix)]
+fn send_signal(pid: u32, group: bool, signal: Signal) {
+    unsafe {
+        if group {
+            libc::killpg(pid as libc::pid_t, signal.raw());
+        } else {
+            libc::kill(pid as libc::pid_t, signal.raw());
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn send_signal(_pid: u32, _group: bool, _signal: Signal) {}
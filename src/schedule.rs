@@ -0,0 +1,209 @@
+//! Running multiple tasks, respecting their dependencies (`depends`) and
+//! ordering constraints (`after`). Independent tasks run concurrently, limited
+//! by the number of jobs (`-j`).
+
+use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
+    future::Future,
+    num::NonZeroUsize,
+    pin::Pin,
+    sync::{Arc, Mutex},
+};
+use futures::stream::{FuturesUnordered, StreamExt};
+use tokio::sync::{OnceCell, Semaphore};
+use crate::{
+    Task, TaskArgs,
+    prelude::*,
+    op::Outcome,
+};
+
+
+/// Keeps track of tasks that were started by the scheduler and their outcome.
+/// Stored in the root frame, so that each task only runs once per floof
+/// invocation, even if multiple tasks depend on it. Operations can insert a
+/// new instance into their frame to reset this (e.g. `watch` does that for
+/// every run, as files have changed).
+#[derive(Debug, Clone, Default)]
+pub struct DependencyRuns(Arc<Mutex<HashMap<String, Arc<OnceCell<Outcome>>>>>);
+
+/// Limits the number of tasks the scheduler runs at the same time across the
+/// whole floof invocation. Stored in the root frame.
+///
+/// Like in make's jobserver, each `run_tasks` call has one implicit job slot,
+/// as its caller is waiting for it anyway (e.g. a task waiting for its
+/// dependencies). All additional tasks that run concurrently need a permit of
+/// the shared semaphore.
+#[derive(Debug, Clone)]
+pub struct Jobs(Arc<Semaphore>);
+
+impl Jobs {
+    pub fn new(jobs: NonZeroUsize) -> Self {
+        Self(Arc::new(Semaphore::new(jobs.get() - 1)))
+    }
+}
+
+/// Runs the given tasks and all their (transitive) dependencies. Each task
+/// only starts once all its dependencies have finished successfully and all
+/// tasks it is ordered `after` (if scheduled at all) have finished. `args` are
+/// only passed to the tasks in `tasks` that have a parameter of that name.
+///
+/// If one task fails, all other running tasks are cancelled.
+pub async fn run_tasks(ctx: &Context, tasks: &[String], args: &TaskArgs) -> Result<Outcome> {
+    let config = &ctx.config;
+    let jobs = ctx.get_closest_var::<Jobs>();
+
+    // Collect all tasks that need to run.
+    let mut scheduled = BTreeSet::new();
+    let mut stack = tasks.iter().map(|s| s.as_str()).collect::<Vec<_>>();
+    while let Some(name) = stack.pop() {
+        if scheduled.insert(name) {
+            stack.extend(config.tasks[name].depends.iter().map(|s| s.as_str()));
+        }
+    }
+
+    // Build the graph: for each task, the number of tasks it's waiting for
+    // and the tasks that are waiting for it.
+    let mut waiting_for = HashMap::new();
+    let mut successors = HashMap::<_, Vec<_>>::new();
+    for &name in &scheduled {
+        let task = &config.tasks[name];
+        let preds = task.depends.iter()
+            .chain(task.after.iter().filter(|t| scheduled.contains(t.as_str())))
+            .map(|s| s.as_str())
+            .collect::<BTreeSet<_>>();
+
+        waiting_for.insert(name, preds.len());
+        for pred in preds {
+            successors.entry(pred).or_default().push(name);
+        }
+    }
+
+    let mut ready = scheduled.iter()
+        .copied()
+        .filter(|name| waiting_for[name] == 0)
+        .collect::<VecDeque<_>>();
+    let mut running = FuturesUnordered::new();
+    let mut implicit_free = true;
+    let mut permit = None;
+    loop {
+        while !ready.is_empty() {
+            // Use the implicit job slot if it's free. Otherwise, a permit is
+            // required.
+            let implicit = implicit_free;
+            let task_permit = match (implicit, permit.take()) {
+                (true, _) => None,
+                (false, Some(permit)) => Some(permit),
+                (false, None) => match jobs.as_ref().map(|j| j.0.clone().try_acquire_owned()) {
+                    Some(Ok(permit)) => Some(permit),
+                    _ => break,
+                },
+            };
+            implicit_free = false;
+            let name = ready.pop_front().unwrap();
+
+            let task = &config.tasks[name];
+            let task_args = if tasks.iter().any(|t| t == name) {
+                args_for(task, args)
+            } else {
+                TaskArgs::new()
+            };
+
+            running.push(async move {
+                let _permit = task_permit;
+                (name, implicit, run_once(ctx, name, task_args).await)
+            });
+        }
+
+        // Wait for a running task to finish or, if tasks are ready, for a
+        // permit to be released (e.g. by a task started elsewhere).
+        let next = match &jobs {
+            Some(jobs) if !ready.is_empty() => tokio::select! {
+                next = running.next() => next,
+                new_permit = jobs.0.clone().acquire_owned() => {
+                    permit = Some(new_permit.expect("bug: job semaphore closed"));
+                    continue;
+                }
+            },
+            _ => running.next().await,
+        };
+        let (name, implicit, result) = match next {
+            Some(v) => v,
+            None => break,
+        };
+        if implicit {
+            implicit_free = true;
+        }
+
+        // In case of error or failure, we return here, which drops (and thus
+        // cancels) all other running tasks.
+        let outcome = result?;
+        if !outcome.is_success() {
+            let dependents = tasks.iter()
+                .filter(|t| *t != name && depends_on(ctx, t, name))
+                .map(|t| format!("'{}'", t))
+                .collect::<Vec<_>>();
+            let ctx = ctx.fork_task(name);
+            if dependents.is_empty() {
+                msg!(warn [ctx] - "task failed");
+            } else {
+                msg!(warn [ctx] - "task failed (required by {})", dependents.join(", "));
+            }
+            if !running.is_empty() {
+                msg!(stop [ctx] - "cancelling {} other running task(s)", running.len());
+            }
+
+            return Ok(outcome);
+        }
+
+        for &succ in successors.get(name).into_iter().flatten() {
+            let count = waiting_for.get_mut(succ).unwrap();
+            *count -= 1;
+            if *count == 0 {
+                ready.push_back(succ);
+            }
+        }
+    }
+
+    Ok(Outcome::Success)
+}
+
+/// Returns the arguments of `args` that are passed to `task`, i.e. the ones for
+/// which it has a parameter.
+pub fn args_for(task: &Task, args: &TaskArgs) -> TaskArgs {
+    args.iter()
+        .filter(|(param, _)| task.params.contains_key(*param))
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect()
+}
+
+/// Returns whether `task` (transitively) depends on `dep`.
+fn depends_on(ctx: &Context, task: &str, dep: &str) -> bool {
+    ctx.config.tasks[task].depends.iter().any(|d| d == dep || depends_on(ctx, d, dep))
+}
+
+/// Runs the task with the given name, unless it already ran in the scope of
+/// the closest `DependencyRuns`. In that case, the outcome of the previous run
+/// is returned. If it is currently running, this waits for it to finish.
+fn run_once<'a>(
+    ctx: &'a Context,
+    name: &'a str,
+    args: TaskArgs,
+) -> Pin<Box<dyn Future<Output = Result<Outcome>> + Send + 'a>> {
+    // This function returns a boxed future as it's indirectly recursive.
+    Box::pin(async move {
+        let runs = ctx.get_closest_var::<DependencyRuns>().expect("bug: no dependency runs");
+        let cell = runs.0.lock()
+            .expect("dependency runs poisoned :(")
+            .entry(name.to_owned())
+            .or_default()
+            .clone();
+
+        if let Some(outcome) = cell.get() {
+            verbose!(- [ctx.fork_task(name)] - "already ran → skipping");
+            return Ok(*outcome);
+        }
+
+        let task = &ctx.config.tasks[name];
+        cell.get_or_try_init(|| task.run(ctx, &args)).await.copied()
+    })
+}
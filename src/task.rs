@@ -1,41 +1,331 @@
+use std::{collections::BTreeMap, fmt, path::PathBuf};
+use serde::{Deserializer, Deserialize, de::{self, MapAccess, SeqAccess, Visitor}};
+use serde_yaml::{Mapping, Value};
 use crate::{
     Config, Operations,
     prelude::*,
-    op::{self, Outcome},
+    diagnostic::{Diagnostic, Location, OperationTree},
+    op::{self, ConfigDir, Operation, Outcome, ScalarString, WorkDir},
+    schedule,
 };
 
 
+/// Arguments for the parameters of a task, by parameter name.
+pub type TaskArgs = BTreeMap<String, String>;
+
 #[derive(Debug)]
 pub struct Task {
     pub name: String,
+
+    /// Short explanation of what the task does, shown by `floof list`.
+    pub description: Option<String>,
+
+    pub params: BTreeMap<String, Param>,
+
+    /// Tasks that have to run before this task. Each dependency runs at most
+    /// once, see `schedule::DependencyRuns`.
+    pub depends: Vec<String>,
+
+    /// Tasks that, if they are scheduled in the same run, have to finish
+    /// before this task starts. Unlike `depends`, this does not cause these
+    /// tasks to run.
+    pub after: Vec<String>,
+
     pub operations: Operations,
+
+    /// For tasks of included files: the directory of that file, relative to
+    /// the directory of the main configuration file. It is the default
+    /// workdir of the task. `None` for tasks of the main file.
+    pub dir: Option<PathBuf>,
+
+    /// Location of the task name in the configuration file. `None` if unknown.
+    pub location: Option<Location>,
+
+    /// Locations of the operations (including nested ones) in the
+    /// configuration file, in the same order as `operations`. Empty if
+    /// unknown.
+    pub operation_locations: Vec<OperationTree<Location>>,
+}
+
+/// Error context referring to a (possibly nested) operation of a task. Used to
+/// find the location of validation errors in the configuration file.
+#[derive(Debug)]
+pub struct AtOperation {
+    /// Indices into the list of operations of each level, see
+    /// `OperationTree::find`.
+    pub path: Vec<usize>,
+    pub message: String,
+}
+
+impl AtOperation {
+    /// Adds the context for the operation `op` with the given index to `e`.
+    /// If `e` already refers to an operation nested in `op`, the index is
+    /// prepended to its path.
+    pub fn wrap(e: Error, index: usize, op: &dyn Operation) -> Error {
+        let mut path = vec![index];
+        if let Some(nested) = e.downcast_ref::<AtOperation>() {
+            path.extend(&nested.path);
+        }
+
+        e.context(AtOperation {
+            path,
+            message: format!("invalid configuration for operation '{}'", op.keyword()),
+        })
+    }
+}
+
+impl fmt::Display for AtOperation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// A parameter of a task. When the task runs, each parameter is available as
+/// user defined variable.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(from = "RawParam")]
+pub struct Param {
+    pub ty: ParamType,
+
+    /// The default value. If `None`, the parameter is required.
+    pub default: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawParam {
+    Default(Option<ScalarString>),
+    Explicit(ExplicitParam),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ExplicitParam {
+    #[serde(rename = "type", default)]
+    ty: ParamType,
+    default: Option<ScalarString>,
+}
+
+impl From<RawParam> for Param {
+    fn from(src: RawParam) -> Self {
+        match src {
+            RawParam::Default(default) => Self {
+                ty: ParamType::default(),
+                default: default.map(|s| s.0),
+            },
+            RawParam::Explicit(e) => Self { ty: e.ty, default: e.default.map(|s| s.0) },
+        }
+    }
+}
+
+/// The type of a task parameter. Arguments are checked against that type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ParamType {
+    #[default]
+    String,
+    Int,
+    Bool,
+    /// A path, which is resolved like the path of `set-workdir`. The variable
+    /// holds the resulting absolute path.
+    Path,
+}
+
+impl ParamType {
+    /// The name as written in the configuration file.
+    fn name(self) -> &'static str {
+        match self {
+            Self::String => "string",
+            Self::Int => "int",
+            Self::Bool => "bool",
+            Self::Path => "path",
+        }
+    }
+
+    /// Checks that `value` is valid for this type and returns the normalized
+    /// value. Paths are resolved against the workdir of `ctx`.
+    fn check(self, value: &str, ctx: &Context) -> Result<String> {
+        match self {
+            Self::Path => Ok(ctx.join_workdir(value).display().to_string()),
+            _ => self.check_syntax(value),
+        }
+    }
+
+    /// Like `check`, but without resolving paths.
+    fn check_syntax(self, value: &str) -> Result<String> {
+        match self {
+            Self::String | Self::Path => Ok(value.to_owned()),
+            Self::Int => value.parse::<i64>()
+                .map(|i| i.to_string())
+                .map_err(|_| anyhow!("'{}' is not a valid integer", value)),
+            Self::Bool => match value {
+                "true" | "yes" | "1" => Ok("true".into()),
+                "false" | "no" | "0" => Ok("false".into()),
+                _ => Err(anyhow!("'{}' is not a valid boolean", value)),
+            },
+        }
+    }
 }
 
 impl Task {
-    pub fn validate(&self, config: &Config) -> Result<()> {
-        for op in &self.operations {
-            op.validate(op::ParentKind::Task(&self.name), config)
-                .context(format!("invalid configuration for operation '{}'", op.keyword()))?;
+    /// Hidden tasks are helpers that are not meant to be run directly. They
+    /// are marked as such in `floof list`. For tasks of included files, the
+    /// name without namespace counts.
+    pub fn is_hidden(&self) -> bool {
+        self.name.rsplit(':').next().unwrap_or_default().starts_with('_')
+    }
+
+    /// Returns the task definition in configuration file syntax. Tasks that
+    /// only consist of operations are described by the short form (a list).
+    pub fn describe(&self) -> Value {
+        let operations = op::describe_all(&self.operations);
+        if self.description.is_none()
+            && self.params.is_empty()
+            && self.depends.is_empty()
+            && self.after.is_empty()
+        {
+            return operations;
+        }
+
+        let params = self.params.iter()
+            .map(|(name, param)| {
+                let value = match param.ty {
+                    ParamType::String => op::optional(param.default.clone()),
+                    ty => op::fields([
+                        ("type", ty.name().into()),
+                        ("default", op::optional(param.default.clone())),
+                    ]),
+                };
+                (name.clone().into(), value)
+            })
+            .collect::<Mapping>();
+        let list = |tasks: &[String]| -> Value {
+            tasks.iter().cloned().map(Value::from).collect()
+        };
+
+        op::fields([
+            ("description", op::optional(self.description.clone())),
+            ("params", if params.is_empty() { Value::Null } else { params.into() }),
+            ("depends", if self.depends.is_empty() { Value::Null } else { list(&self.depends) }),
+            ("after", if self.after.is_empty() { Value::Null } else { list(&self.after) }),
+            ("run", operations),
+        ])
+    }
+
+    /// Returns all errors in the definition of this task.
+    pub fn validate(&self, config: &Config) -> Vec<Error> {
+        let mut errors = Vec::new();
+        for (name, param) in &self.params {
+            if let Some(default) = &param.default {
+                if let Err(e) = param.ty.check_syntax(default) {
+                    errors.push(e.context(format!("invalid default value for parameter '{}'", name)));
+                }
+            }
+        }
+
+        for dep in &self.depends {
+            match config.tasks.get(dep) {
+                None => errors.push(anyhow!("dependency '{}' does not exist", dep)),
+                Some(task) => if let Err(e) = task.check_arg_names(&TaskArgs::new()) {
+                    errors.push(e.context(format!("task '{}' cannot be used as dependency", dep)));
+                },
+            }
+        }
+
+        for unknown in self.after.iter().filter(|t| !config.tasks.contains_key(*t)) {
+            errors.push(anyhow!("task '{}' in `after` does not exist", unknown));
+        }
+
+        for (index, op) in self.operations.iter().enumerate() {
+            let op_errors = op.validate(op::ParentKind::Task(&self.name), config);
+            errors.extend(op_errors.into_iter().map(|e| AtOperation::wrap(e, index, &**op)));
+        }
+
+        errors
+    }
+
+    /// Checks the arguments for this task (see `check_arg_names`) and that
+    /// their values are valid for the type of the parameter.
+    pub fn check_args(&self, args: &TaskArgs) -> Result<()> {
+        self.check_arg_names(args)?;
+        for (name, value) in args {
+            self.params[name].ty.check_syntax(value).context(format!(
+                "invalid argument for parameter '{}' of task '{}'",
+                name,
+                self.name,
+            ))?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks that all arguments refer to existing parameters and that all
+    /// required parameters are given.
+    pub fn check_arg_names(&self, args: &TaskArgs) -> Result<()> {
+        if let Some(unknown) = args.keys().find(|arg| !self.params.contains_key(*arg)) {
+            bail!("task '{}' has no parameter '{}'", self.name, unknown);
+        }
+
+        for (name, param) in &self.params {
+            if param.default.is_none() && !args.contains_key(name) {
+                bail!("required parameter '{}' of task '{}' not specified", name, self.name);
+            }
         }
 
         Ok(())
     }
 
-    pub async fn run(&self, ctx: &Context) -> Result<Outcome> {
+    pub async fn run(&self, ctx: &Context, args: &TaskArgs) -> Result<Outcome> {
+        // Check the arguments before running anything.
+        self.check_args(args)?;
+
+        // Run dependencies first. They run in the parent context, as they
+        // should not depend on the arguments of this task. If this task was
+        // started by the scheduler, they already ran and are skipped.
+        let outcome = schedule::run_tasks(ctx, &self.depends, &TaskArgs::new()).await?;
+        if !outcome.is_success() {
+            return Ok(outcome);
+        }
+
         let ctx = ctx.fork_task(&self.name);
+        if let Some(dir) = &self.dir {
+            let base = ctx.root_frame().get_var::<ConfigDir>().expect("bug: no root config dir");
+            let dir = base.0.join(dir);
+            ctx.top_frame.insert_var(ConfigDir(dir.clone()));
+            ctx.top_frame.insert_var(WorkDir(dir));
+        }
         verbose!(- [ctx] - "Starting task");
 
-        for op in &self.operations {
-            let outcome = op.run(&ctx).await.with_context(|| {
-                // TODO: nicer output of the operation
-                format!("failed to run operation for task '{}':\n{:#?}", self.name, op)
+        // Make the parameters available as variables.
+        for (name, param) in &self.params {
+            let value = args.get(name)
+                .or(param.default.as_ref())
+                .expect("bug: missing argument after check");
+            let value = param.ty.check(value, &ctx)
+                .context(format!("invalid argument for parameter '{}' of task '{}'", name, self.name))?;
+            ctx.set_user_var(name, value);
+        }
+
+        for (index, op) in self.operations.iter().enumerate() {
+            let outcome = op.run(&ctx).await.map_err(|e| {
+                match self.operation_locations.get(index) {
+                    Some(OperationTree { value: location, .. }) => e.context(Diagnostic::new(
+                        format!("failed to run operation '{}' of task '{}'", op.keyword(), self.name),
+                        location.clone(),
+                    )),
+                    None => e.context(format!(
+                        "failed to run operation for task '{}':\n{}",
+                        self.name,
+                        crate::ui::indented(op),
+                    )),
+                }
             })?;
 
             if !outcome.is_success() {
                 verbose!(
-                    - [ctx] - "'{}' operation failed → stopping (no further operations of \
-                        this task are ran)",
-                    op.keyword(),
+                    - [ctx] - "operation failed → stopping (no further operations of \
+                        this task are ran):\n{}",
+                    crate::ui::indented(op),
                 );
                 return Ok(Outcome::Failure)
             }
@@ -46,3 +336,90 @@ impl Task {
         Ok(Outcome::Success)
     }
 }
+
+/// A task as specified in the configuration: either just a list of operations
+/// or a map with additional properties.
+pub struct RawTask {
+    description: Option<String>,
+    params: BTreeMap<String, Param>,
+    depends: Vec<String>,
+    after: Vec<String>,
+    operations: Operations,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ExplicitTask {
+    description: Option<String>,
+    #[serde(default)]
+    params: BTreeMap<String, Param>,
+    #[serde(default)]
+    depends: Vec<String>,
+    #[serde(default)]
+    after: Vec<String>,
+    run: Operations,
+}
+
+impl RawTask {
+    pub fn into_task(self, name: String) -> Task {
+        Task {
+            name,
+            description: self.description,
+            params: self.params,
+            depends: self.depends,
+            after: self.after,
+            operations: self.operations,
+            dir: None,
+            location: None,
+            operation_locations: vec![],
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for RawTask {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct TaskVisitor;
+        impl<'de> Visitor<'de> for TaskVisitor {
+            type Value = RawTask;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a list of operations or a map with a `run` field")
+            }
+
+            fn visit_seq<A>(self, seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: SeqAccess<'de>,
+            {
+                let operations = Deserialize::deserialize(de::value::SeqAccessDeserializer::new(seq))?;
+                Ok(RawTask {
+                    description: None,
+                    params: BTreeMap::new(),
+                    depends: vec![],
+                    after: vec![],
+                    operations,
+                })
+            }
+
+            fn visit_map<A>(self, map: A) -> Result<Self::Value, A::Error>
+            where
+                A: MapAccess<'de>,
+            {
+                let task: ExplicitTask
+                    = Deserialize::deserialize(de::value::MapAccessDeserializer::new(map))?;
+                Ok(RawTask {
+                    description: task.description,
+                    params: task.params,
+                    depends: task.depends,
+                    after: task.after,
+                    operations: task.run,
+                })
+            }
+        }
+
+        // See `impl_deserialize_for_op` for why `deserialize_any` is fine.
+        deserializer.deserialize_any(TaskVisitor)
+    }
+}
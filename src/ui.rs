@@ -3,6 +3,7 @@ use once_cell::sync::OnceCell;
 use bunt::termcolor::{Buffer, BufferWriter};
 use crate::{
     Args, Context,
+    cfg::Config,
     prelude::*,
 };
 
@@ -75,6 +76,49 @@ pub fn print_prefix(
     Ok(())
 }
 
+/// Returns the displayed value with each line indented by four spaces. Used
+/// to show multi-line values (e.g. operations) in messages.
+pub fn indented(value: impl std::fmt::Display) -> String {
+    value.to_string()
+        .lines()
+        .map(|line| format!("    {}", line))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Prints all tasks of the configuration with their descriptions, sorted by
+/// name. Hidden tasks are listed last.
+pub fn print_task_list(config: &Config) -> Result<(), std::io::Error> {
+    let w = WRITER.get().expect("bug: ui not initialized yet");
+    let mut buf = w.buffer();
+
+    let mut tasks = config.tasks.values().collect::<Vec<_>>();
+    tasks.sort_by_key(|t| (t.is_hidden(), &t.name));
+    let width = tasks.iter().map(|t| t.name.chars().count()).max().unwrap_or(0);
+
+    for task in tasks {
+        let name = format!("{:1$}", task.name, width);
+        if task.is_hidden() {
+            bunt::write!(buf, "  {$black+intense}{}{/$}", name)?;
+        } else {
+            bunt::write!(buf, "  {[blue+intense+bold]}", name)?;
+        }
+
+        if let Some(description) = &task.description {
+            write!(buf, "  {}", description)?;
+        }
+        if task.name == "default" {
+            bunt::write!(buf, "  {$green}(default){/$}")?;
+        }
+        if task.is_hidden() {
+            bunt::write!(buf, "  {$black+intense}(hidden){/$}")?;
+        }
+        writeln!(buf)?;
+    }
+
+    w.print(&buf)
+}
+
 /// Emit a message.
 macro_rules! msg {
     (@to_option -) => { None };